@@ -1,49 +1,219 @@
-#![feature(test)]
-
-extern crate test;
+#[macro_use]
+extern crate criterion;
 extern crate http_muncher;
+extern crate httparse;
 
-use test::Bencher;
+use criterion::{black_box, Criterion, Throughput};
 
 use http_muncher::{ParserHandler, Parser};
 
-#[bench]
-fn bench_request_parser(b: &mut Bencher) {
-    struct TestRequestParser;
+// Touches every callback payload so that the measurements include the cost of
+// crossing the FFI boundary and building the slices, but nothing else.
+#[derive(Default)]
+struct CountingHandler {
+    bytes: usize,
+    messages: usize,
+}
 
-    impl ParserHandler for TestRequestParser {
-        fn on_url(&mut self, _: &mut Parser, url: &[u8]) -> bool {
-            assert_eq!(b"/say_hello", url);
-            true
-        }
+impl ParserHandler for CountingHandler {
+    fn on_url(&mut self, _: &mut Parser, url: &[u8]) -> bool {
+        self.bytes += url.len();
+        true
+    }
 
-        fn on_header_field(&mut self, _: &mut Parser, hdr: &[u8]) -> bool {
-            assert!(hdr == b"Host" || hdr == b"Content-Length");
-            true
-        }
+    fn on_status(&mut self, _: &mut Parser, status: &[u8]) -> bool {
+        self.bytes += status.len();
+        true
+    }
 
-        fn on_header_value(&mut self, _: &mut Parser, val: &[u8]) -> bool {
-            assert!(val == b"localhost.localdomain" || val == b"11");
-            true
-        }
+    fn on_header_field(&mut self, _: &mut Parser, hdr: &[u8]) -> bool {
+        self.bytes += hdr.len();
+        true
+    }
+
+    fn on_header_value(&mut self, _: &mut Parser, val: &[u8]) -> bool {
+        self.bytes += val.len();
+        true
+    }
 
-        fn on_body(&mut self, _: &mut Parser, body: &[u8]) -> bool {
-            assert_eq!(body, b"Hello world");
-            true
-        }
+    fn on_body(&mut self, _: &mut Parser, body: &[u8]) -> bool {
+        self.bytes += body.len();
+        true
     }
 
-    let req = b"POST /say_hello HTTP/1.1\r\nContent-Length: 11\r\nHost: localhost.localdomain\r\n\r\nHello world";
+    fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+        self.messages += 1;
+        true
+    }
+}
+
+const SMALL_POST: &[u8] = b"POST /say_hello HTTP/1.1\r\nContent-Length: 11\r\nHost: localhost.localdomain\r\n\r\nHello world";
+
+const BROWSER_GET: &[u8] = b"GET /wp-content/uploads/2010/03/hello-kitty-darth-vader-pink.jpg HTTP/1.1\r\n\
+Host: www.kittyhell.com\r\n\
+User-Agent: Mozilla/5.0 (Macintosh; U; Intel Mac OS X 10_6_3; ja-JP-mac; rv:1.9.2.3) Gecko/20100401 Firefox/3.6.3 Pathtraq/0.9\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+Accept-Language: ja,en-us;q=0.7,en;q=0.3\r\n\
+Accept-Encoding: gzip,deflate\r\n\
+Accept-Charset: Shift_JIS,utf-8;q=0.7,*;q=0.7\r\n\
+Keep-Alive: 115\r\n\
+Connection: keep-alive\r\n\
+Cookie: wp_ozh_wsa_visits=2; wp_ozh_wsa_visit_lasttime=xxxxxxxxxx; __utma=xxxxxxxxx.xxxxxxxxxx.xxxxxxxxxx.xxxxxxxxxx.xxxxxxxxxx.x; __utmz=xxxxxxxxx.xxxxxxxxxx.x.x.utmccn=(referral)|utmcsr=reader.livedoor.com|utmcct=/reader/|utmcmd=referral\r\n\r\n";
+
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Date: Mon, 23 May 2005 22:38:34 GMT\r\n\
+Server: Apache/1.3.3.7 (Unix) (Red-Hat/Linux)\r\n\
+Last-Modified: Wed, 08 Jan 2003 23:11:55 GMT\r\n\
+ETag: \"3f80f-1b6-3e1cb03b\"\r\n\
+Content-Type: text/html; charset=UTF-8\r\n\
+Content-Length: 129\r\n\
+Accept-Ranges: bytes\r\n\
+Connection: close\r\n\r\n\
+<html>\n<head>\n  <title>An Example Page</title>\n</head>\n<body>\n  Hello World, this is a very simple HTML document.\n</body>\n</html>";
+
+fn large_headers_request() -> Vec<u8> {
+    let mut req = b"GET /api/v1/resources?limit=100&offset=200 HTTP/1.1\r\nHost: bench.local\r\n".to_vec();
+    for i in 0..64 {
+        req.extend_from_slice(format!("X-Custom-Header-{}: {}\r\n", i, "v".repeat(48)).as_bytes());
+    }
+    req.extend_from_slice(b"\r\n");
+    req
+}
+
+fn chunked_response() -> Vec<u8> {
+    let mut res = b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\n\r\n"
+        .to_vec();
+    let chunk = vec![b'x'; 4096];
+    for _ in 0..16 {
+        res.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        res.extend_from_slice(&chunk);
+        res.extend_from_slice(b"\r\n");
+    }
+    res.extend_from_slice(b"0\r\n\r\n");
+    res
+}
+
+fn pipelined_requests() -> Vec<u8> {
+    let mut stream = Vec::new();
+    for _ in 0..32 {
+        stream.extend_from_slice(BROWSER_GET);
+    }
+    stream
+}
+
+fn parse_whole(parser: &mut Parser, data: &[u8]) -> usize {
+    let mut handler = CountingHandler::default();
+    let parsed = parser.parse(&mut handler, data);
+    assert_eq!(parsed, data.len());
+    assert!(!parser.has_error());
+    handler.bytes
+}
+
+fn bench_corpus(c: &mut Criterion, name: &str, data: &[u8], new_parser: fn() -> Parser) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("whole", |b| {
+        b.iter(|| {
+            let mut parser = new_parser();
+            black_box(parse_whole(&mut parser, black_box(data)))
+        })
+    });
+
+    group.finish();
+}
 
-    let mut handler = TestRequestParser;
+fn bench_small_post(c: &mut Criterion) {
+    bench_corpus(c, "small_post", SMALL_POST, Parser::request);
+}
 
-    b.iter(move || {
-        let mut parser = Parser::request();
-        let parsed = parser.parse(&mut handler, req);
+fn bench_large_headers(c: &mut Criterion) {
+    bench_corpus(c, "large_headers", &large_headers_request(), Parser::request);
+}
+
+fn bench_chunked(c: &mut Criterion) {
+    bench_corpus(c, "chunked_response", &chunked_response(), Parser::response);
+}
 
-        assert!(parsed > 0);
-        assert!(!parser.has_error());
-        assert_eq!((1, 1), parser.http_version());
-        assert_eq!("POST", parser.http_method());
+fn bench_response(c: &mut Criterion) {
+    bench_corpus(c, "response", RESPONSE, Parser::response);
+}
+
+fn bench_pipelined(c: &mut Criterion) {
+    let stream = pipelined_requests();
+
+    let mut group = c.benchmark_group("pipelined_keep_alive");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+
+    group.bench_function("whole", |b| {
+        b.iter(|| {
+            let mut parser = Parser::request();
+            let mut handler = CountingHandler::default();
+            parser.parse(&mut handler, black_box(&stream));
+            assert_eq!(handler.messages, 32);
+            black_box(handler.bytes)
+        })
     });
+
+    group.finish();
 }
+
+fn bench_byte_at_a_time(c: &mut Criterion) {
+    let mut group = c.benchmark_group("byte_at_a_time");
+    group.throughput(Throughput::Bytes(BROWSER_GET.len() as u64));
+
+    group.bench_function("request", |b| {
+        b.iter(|| {
+            let mut parser = Parser::request();
+            let mut handler = CountingHandler::default();
+            for byte in BROWSER_GET.chunks(1) {
+                assert_eq!(parser.parse(&mut handler, black_box(byte)), 1);
+            }
+            assert_eq!(handler.messages, 1);
+            black_box(handler.bytes)
+        })
+    });
+
+    group.finish();
+}
+
+// `httparse` only parses message heads, so the comparison is limited to
+// requests without a body.
+fn bench_httparse_baseline(c: &mut Criterion) {
+    let large = large_headers_request();
+    let corpora: [(&str, &[u8]); 2] = [("browser_get", BROWSER_GET), ("large_headers", &large)];
+
+    for &(name, data) in corpora.iter() {
+        let mut group = c.benchmark_group(format!("request_head/{}", name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+
+        group.bench_function("http_muncher", |b| {
+            b.iter(|| {
+                let mut parser = Parser::request();
+                black_box(parse_whole(&mut parser, black_box(data)))
+            })
+        });
+
+        group.bench_function("httparse", |b| {
+            b.iter(|| {
+                let mut headers = [httparse::EMPTY_HEADER; 96];
+                let mut req = httparse::Request::new(&mut headers);
+                let status = req.parse(black_box(data)).unwrap();
+                assert!(status.is_complete());
+                black_box(req.headers.len())
+            })
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches,
+                 bench_small_post,
+                 bench_large_headers,
+                 bench_chunked,
+                 bench_response,
+                 bench_pipelined,
+                 bench_byte_at_a_time,
+                 bench_httparse_baseline);
+criterion_main!(benches);
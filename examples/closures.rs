@@ -0,0 +1,25 @@
+extern crate http_muncher;
+use std::str;
+
+use http_muncher::{Handler, Parser};
+
+// The same header printer as in `parser.rs`, built from closures instead of a
+// `ParserHandler` implementation.
+fn main() {
+    let mut handler = Handler::builder()
+        .on_header_field(|_, header| {
+            println!("{}: ", str::from_utf8(header).unwrap());
+            true
+        })
+        .on_header_value(|_, value| {
+            println!("\t{}", str::from_utf8(value).unwrap());
+            true
+        })
+        .build();
+
+    let mut parser = Parser::request();
+    parser.parse(&mut handler, b"GET / HTTP/1.1\r\nContent-Type: text/plain\r\nHello: World\r\n\r\n");
+
+    let (http_major, http_minor) = parser.http_version();
+    println!("\nHTTP v.{}.{}", http_major, http_minor);
+}
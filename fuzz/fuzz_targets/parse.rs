@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use http_muncher_fuzz::{drive, Mode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&mode, data)) = data.split_first() {
+        let mode = match mode % 3 {
+            0 => Mode::Request,
+            1 => Mode::Response,
+            _ => Mode::Both,
+        };
+        drive(mode, data, &[], &[]);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+use http_muncher_fuzz::{drive, Action, Mode};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    mode: Mode,
+    actions: Vec<Action>,
+    splits: Vec<usize>,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    drive(input.mode, &input.data, &input.splits, &input.actions);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+use http_muncher_fuzz::{drive, Mode};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    mode: Mode,
+    splits: Vec<usize>,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    drive(input.mode, &input.data, &input.splits, &[]);
+});
@@ -0,0 +1,162 @@
+//! Shared drivers for the fuzz targets.
+//!
+//! Every target funnels its input through `drive`, which feeds the parser
+//! chunk by chunk and checks the invariants the FFI glue has to uphold: the
+//! parser never reports more bytes consumed than it was given, and every slice
+//! handed to a data callback lies within the chunk passed to `Parser::parse`.
+
+use http_muncher::{Parser, ParserHandler};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Mode {
+    Request,
+    Response,
+    Both,
+}
+
+impl Mode {
+    pub fn parser(self) -> Parser {
+        match self {
+            Mode::Request => Parser::request(),
+            Mode::Response => Parser::response(),
+            Mode::Both => Parser::request_and_response(),
+        }
+    }
+}
+
+/// What a callback should answer when it's invoked.
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum Action {
+    Continue,
+    Stop,
+    Pause,
+}
+
+/// Answers callbacks according to a script, continuing once it runs out.
+pub struct ScriptedHandler<'a> {
+    actions: &'a [Action],
+    calls: usize,
+    chunk: (usize, usize),
+}
+
+impl<'a> ScriptedHandler<'a> {
+    pub fn new(actions: &'a [Action]) -> ScriptedHandler<'a> {
+        ScriptedHandler {
+            actions,
+            calls: 0,
+            chunk: (0, 0),
+        }
+    }
+
+    fn next(&mut self, parser: &mut Parser) -> bool {
+        let action = self.actions.get(self.calls).cloned().unwrap_or(Action::Continue);
+        self.calls += 1;
+
+        match action {
+            Action::Continue => true,
+            Action::Stop => false,
+            Action::Pause => {
+                parser.pause();
+                true
+            }
+        }
+    }
+
+    fn data(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        let start = data.as_ptr() as usize;
+        assert!(start >= self.chunk.0 && start + data.len() <= self.chunk.1,
+                "callback slice lies outside of the parsed chunk");
+        self.next(parser)
+    }
+}
+
+impl<'a> ParserHandler for ScriptedHandler<'a> {
+    fn on_url(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.data(parser, data)
+    }
+
+    fn on_status(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.data(parser, data)
+    }
+
+    fn on_header_field(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.data(parser, data)
+    }
+
+    fn on_header_value(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.data(parser, data)
+    }
+
+    fn on_body(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.data(parser, data)
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.next(parser)
+    }
+
+    fn on_message_begin(&mut self, parser: &mut Parser) -> bool {
+        self.next(parser)
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        self.next(parser)
+    }
+
+    fn on_chunk_header(&mut self, parser: &mut Parser) -> bool {
+        self.next(parser)
+    }
+
+    fn on_chunk_complete(&mut self, parser: &mut Parser) -> bool {
+        self.next(parser)
+    }
+}
+
+/// Splits `data` at the given points (taken modulo the input length) and feeds
+/// the chunks to a parser in order, resuming after every pause, then signals EOF.
+pub fn drive(mode: Mode, data: &[u8], splits: &[usize], actions: &[Action]) {
+    let mut points: Vec<usize> = splits.iter().map(|&s| s % (data.len() + 1)).collect();
+    points.push(0);
+    points.push(data.len());
+    points.sort_unstable();
+    points.dedup();
+
+    let mut parser = mode.parser();
+    let mut handler = ScriptedHandler::new(actions);
+
+    for window in points.windows(2) {
+        let mut chunk = &data[window[0]..window[1]];
+
+        loop {
+            handler.chunk = (chunk.as_ptr() as usize, chunk.as_ptr() as usize + chunk.len());
+
+            let consumed = parser.parse(&mut handler, chunk);
+            assert!(consumed <= chunk.len(),
+                    "parser consumed {} bytes out of {}",
+                    consumed,
+                    chunk.len());
+
+            if parser.has_error() && parser.error() == "HPE_PAUSED" {
+                parser.unpause();
+                chunk = &chunk[consumed..];
+                if chunk.is_empty() {
+                    break;
+                }
+                continue;
+            }
+
+            if parser.has_error() || parser.is_upgrade() {
+                return;
+            }
+
+            break;
+        }
+    }
+
+    // A zero-length chunk signals EOF to http-parser, which reports a single
+    // byte as consumed when the message was cut short.
+    handler.chunk = (0, 0);
+    let consumed = parser.parse(&mut handler, &[]);
+    assert!(consumed <= 1);
+}
@@ -0,0 +1,134 @@
+//! Plumbing shared by the handler adapters, which wrap an `inner` handler,
+//! pass most callbacks straight on to it and look at one of the headers.
+
+use headers::HeaderAccumulator;
+
+/// Collects the values of one header while the headers of a message are
+/// passed on to the inner handler.
+#[derive(Debug, Clone)]
+pub(crate) struct HeaderWatch {
+    name: &'static str,
+    accumulator: HeaderAccumulator,
+    values: Vec<Vec<u8>>,
+}
+
+impl HeaderWatch {
+    /// Watches for headers named `name`, compared case-insensitively.
+    pub fn new(name: &'static str) -> HeaderWatch {
+        HeaderWatch {
+            name,
+            accumulator: HeaderAccumulator::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Forgets the previous message's headers.
+    pub fn clear(&mut self) {
+        self.accumulator = HeaderAccumulator::new();
+        self.values.clear();
+    }
+
+    pub fn on_field(&mut self, data: &[u8]) {
+        let header = self.accumulator.on_field(data);
+        self.add(header);
+    }
+
+    pub fn on_value(&mut self, data: &[u8]) {
+        self.accumulator.on_value(data);
+    }
+
+    /// Adds the last header; call it from `on_headers_complete` before using `values`.
+    pub fn finish(&mut self) {
+        let header = self.accumulator.finish();
+        self.add(header);
+    }
+
+    /// Returns the values of the watched header, in the order they were received.
+    pub fn values(&self) -> &[Vec<u8>] {
+        &self.values
+    }
+
+    /// Returns the value of the last watched header.
+    pub fn last(&self) -> Option<&[u8]> {
+        self.values.last().map(|value| &value[..])
+    }
+
+    fn add(&mut self, header: Option<(Vec<u8>, Vec<u8>)>) {
+        if let Some((field, value)) = header {
+            if field.eq_ignore_ascii_case(self.name.as_bytes()) {
+                self.values.push(value);
+            }
+        }
+    }
+}
+
+/// Implements `inner`, `inner_mut` and `into_inner` for an adapter wrapping
+/// a handler of type `$inner` in its `inner` field, or in the field at the
+/// path given after it.
+macro_rules! inner_accessors {
+    ($inner:ident) => {
+        inner_accessors!($inner, inner);
+    };
+    ($inner:ident, $($field:ident).+) => {
+        /// Returns the wrapped handler.
+        pub fn inner(&self) -> &$inner {
+            &self.$($field).+
+        }
+
+        /// Returns the wrapped handler mutably.
+        pub fn inner_mut(&mut self) -> &mut $inner {
+            &mut self.$($field).+
+        }
+
+        /// Unwraps the adapter, returning the wrapped handler.
+        pub fn into_inner(self) -> $inner {
+            self.$($field).+
+        }
+    };
+}
+
+/// Implements `ParserHandler` callbacks that only pass their arguments on to
+/// the `inner` handler. `on_header_field` and `on_header_value` also feed the
+/// adapter's `HeaderWatch` in its `headers` field, unless the list starts
+/// with `unwatched:`.
+macro_rules! pass_through {
+    (unwatched: $($callback:ident),*) => {
+        $(pass_through!(@ unwatched $callback);)*
+    };
+    ($($callback:ident),*) => {
+        $(pass_through!(@ $callback);)*
+    };
+    (@ unwatched on_header_field) => { pass_through!(@ data on_header_field); };
+    (@ unwatched on_header_value) => { pass_through!(@ data on_header_value); };
+    (@ unwatched $callback:ident) => { pass_through!(@ $callback); };
+    (@ on_header_field) => {
+        fn on_header_field(&mut self, parser: &mut $crate::Parser, data: &[u8]) -> bool {
+            self.headers.on_field(data);
+            self.inner.on_header_field(parser, data)
+        }
+    };
+    (@ on_header_value) => {
+        fn on_header_value(&mut self, parser: &mut $crate::Parser, data: &[u8]) -> bool {
+            self.headers.on_value(data);
+            self.inner.on_header_value(parser, data)
+        }
+    };
+    (@ on_url) => { pass_through!(@ data on_url); };
+    (@ on_status) => { pass_through!(@ data on_status); };
+    (@ on_body) => { pass_through!(@ data on_body); };
+    (@ on_message_begin) => { pass_through!(@ notify on_message_begin); };
+    (@ on_headers_complete) => { pass_through!(@ notify on_headers_complete); };
+    (@ on_message_complete) => { pass_through!(@ notify on_message_complete); };
+    (@ on_chunk_header) => { pass_through!(@ notify on_chunk_header); };
+    (@ on_chunk_complete) => { pass_through!(@ notify on_chunk_complete); };
+    (@ data $callback:ident) => {
+        fn $callback(&mut self, parser: &mut $crate::Parser, data: &[u8]) -> bool {
+            self.inner.$callback(parser, data)
+        }
+    };
+    (@ notify $callback:ident) => {
+        fn $callback(&mut self, parser: &mut $crate::Parser) -> bool {
+            self.inner.$callback(parser)
+        }
+    };
+}
@@ -0,0 +1,619 @@
+//! A small blocking HTTP/1.1 client, enabled with the `client` feature.
+//!
+//! Requests are serialized and responses parsed by a `ClientConnection` over
+//! a plain `TcpStream`. Only `http` URLs are supported, and whole bodies are
+//! held in memory. It's meant for talking to local services from tests and
+//! tools rather than to the open internet.
+//!
+//! # Example
+//! ```no_run
+//! use http_muncher::client::{Client, Request};
+//!
+//! let mut client = Client::new().follow_redirects(5);
+//!
+//! let response = client.get("http://127.0.0.1:8080/health").unwrap();
+//! assert_eq!(response.status, 200);
+//!
+//! let request = Request::new("POST", "http://127.0.0.1:8080/items")
+//!     .header("Content-Type", "application/json")
+//!     .body(r#"{"name": "x"}"#);
+//! let response = client.send(request).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str;
+use std::time::Duration;
+
+use headers::Headers;
+use url::{self, Url};
+use {ClientConnection, ClientEvent, ConnectionError};
+
+/// A request to send with `Client::send`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The method, such as `GET`.
+    pub method: String,
+    /// An absolute `http` URL.
+    pub url: String,
+    /// `Host` is added unless it's set here. `Content-Length` is set by the
+    /// client, so any framing headers among these are left out.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// The request body.
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Creates a request without headers or a body.
+    pub fn new(method: &str, url: &str) -> Request {
+        Request {
+            method: method.to_owned(),
+            url: url.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header.
+    pub fn header<V: Into<Vec<u8>>>(mut self, name: &str, value: V) -> Request {
+        self.headers.push((name.to_owned(), value.into()));
+        self
+    }
+
+    /// Sets the body.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Request {
+        self.body = body.into();
+        self
+    }
+}
+
+/// A complete response returned by `Client::send`.
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The status code.
+    pub status: u16,
+    /// The reason phrase, as sent by the server.
+    pub reason: Vec<u8>,
+    /// The HTTP version as `(major, minor)`.
+    pub version: (u16, u16),
+    /// The response headers.
+    pub headers: Headers,
+    /// The response body.
+    pub body: Vec<u8>,
+    /// The URL the response came from, which differs from the request's after redirects.
+    pub url: String,
+}
+
+/// An error returned by `Client`.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting, writing or reading failed, including timeouts.
+    Io(io::Error),
+    /// The request couldn't be serialized, or the response couldn't be parsed.
+    Connection(ConnectionError),
+    /// The URL couldn't be parsed or has no host.
+    InvalidUrl,
+    /// The URL's scheme isn't `http`.
+    UnsupportedScheme,
+    /// The server redirected more times than `Client::follow_redirects` allows.
+    TooManyRedirects,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref err) => write!(f, "I/O error: {}", err),
+            ClientError::Connection(ref err) => write!(f, "{}", err),
+            ClientError::InvalidUrl => f.write_str("invalid URL"),
+            ClientError::UnsupportedScheme => f.write_str("unsupported URL scheme"),
+            ClientError::TooManyRedirects => f.write_str("too many redirects"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ClientError::Io(ref err) => Some(err),
+            ClientError::Connection(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+impl From<ConnectionError> for ClientError {
+    fn from(err: ConnectionError) -> ClientError {
+        ClientError::Connection(err)
+    }
+}
+
+/// The parts of a URL needed to send a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    host: String,
+    port: u16,
+    /// The `Host` header value.
+    authority: String,
+    /// The origin-form request target.
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Target, ClientError> {
+        let parsed = Url::parse(url.as_bytes()).map_err(|_| ClientError::InvalidUrl)?;
+        let text = |part: &[u8]| str::from_utf8(part).map(str::to_owned).map_err(|_| ClientError::InvalidUrl);
+
+        if !parsed.schema().is_some_and(|schema| schema.eq_ignore_ascii_case(b"http")) {
+            return Err(ClientError::UnsupportedScheme);
+        }
+        let host = text(parsed.host().ok_or(ClientError::InvalidUrl)?)?;
+
+        // http-parser strips the brackets around IPv6 addresses.
+        let mut authority = if host.contains(':') { format!("[{}]", host) } else { host.clone() };
+        if let Some(port) = parsed.port() {
+            authority = format!("{}:{}", authority, port);
+        }
+
+        let mut path = text(parsed.path().unwrap_or(b"/"))?;
+        if let Some(query) = parsed.query() {
+            path = format!("{}?{}", path, text(query)?);
+        }
+
+        Ok(Target {
+            host,
+            port: parsed.port().unwrap_or(80),
+            authority,
+            path,
+        })
+    }
+
+    /// Resolves the `Location` of a redirect against this URL, as described
+    /// in RFC 3986 section 5.2.
+    fn join(&self, location: &str) -> Result<Target, ClientError> {
+        // Fragments aren't sent.
+        let location = location.split('#').next().unwrap_or("");
+
+        let mut target = if location.starts_with("//") {
+            Target::parse(&format!("http:{}", location))?
+        } else if Url::parse(location.as_bytes()).is_ok_and(|url| url.schema().is_some()) {
+            Target::parse(location)?
+        } else {
+            let (base_path, base_query) = split_query(&self.path);
+            let (path, query) = split_query(location);
+
+            let (path, query) = if path.is_empty() {
+                (base_path.to_owned(), query.or(base_query))
+            } else if path.starts_with('/') {
+                (path.to_owned(), query)
+            } else {
+                let directory = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+                (format!("{}{}", directory, path), query)
+            };
+            let query = query.map_or(String::new(), |query| format!("?{}", query));
+
+            Target::parse(&format!("http://{}{}{}", self.authority, path, query))?
+        };
+
+        let (path, query) = split_query(&target.path);
+        let mut resolved = String::from_utf8(url::remove_dot_segments(path.as_bytes())).map_err(|_| ClientError::InvalidUrl)?;
+        if let Some(query) = query {
+            resolved = format!("{}?{}", resolved, query);
+        }
+        target.path = resolved;
+        Ok(target)
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}{}", self.authority, self.path)
+    }
+}
+
+/// Splits a request target into its path and query.
+fn split_query(target: &str) -> (&str, Option<&str>) {
+    target.split_once('?').map_or((target, None), |(path, query)| (path, Some(query)))
+}
+
+/// An open connection to a server.
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    conn: ClientConnection,
+}
+
+impl Connection {
+    fn open(target: &Target, timeout: Option<Duration>) -> io::Result<Connection> {
+        let mut last_err = None;
+
+        for addr in (&target.host[..], target.port).to_socket_addrs()? {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(timeout)?;
+                    stream.set_write_timeout(timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(Connection { stream, conn: ClientConnection::new() });
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the host has no addresses")))
+    }
+
+    /// Sends a request and reads its response. Returns whether the connection
+    /// can be reused, and sets `received` once any data has been read.
+    fn exchange(&mut self, method: &str, target: &Target, headers: &[(String, Vec<u8>)], body: &[u8], received: &mut bool)
+        -> Result<(Response, bool), ClientError>
+    {
+        let length = body.len().to_string();
+        let mut head: Vec<(&[u8], &[u8])> = Vec::new();
+
+        if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("host")) {
+            head.push((b"Host", target.authority.as_bytes()));
+        }
+        head.extend(headers.iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("content-length") && !name.eq_ignore_ascii_case("transfer-encoding"))
+            .map(|(name, value)| (name.as_bytes(), &value[..])));
+        if !body.is_empty() || matches!(method, "POST" | "PUT" | "PATCH") {
+            head.push((b"Content-Length", length.as_bytes()));
+        }
+
+        let mut out = self.conn.send_request(method, &target.path, head)?;
+        out.extend(self.conn.send_data(body)?);
+        out.extend(self.conn.end_request()?);
+        self.stream.write_all(&out)?;
+
+        let mut response = None;
+        let mut buf = [0; 8192];
+
+        loop {
+            match self.conn.next_event()? {
+                ClientEvent::Informational { .. } => {}
+                ClientEvent::ResponseHead(head) => {
+                    response = Some(Response {
+                        status: head.status,
+                        reason: head.reason,
+                        version: head.version,
+                        headers: head.headers,
+                        body: Vec::new(),
+                        url: target.url(),
+                    });
+                }
+                ClientEvent::Body(data) => {
+                    if let Some(ref mut response) = response {
+                        response.body.extend_from_slice(&data);
+                    }
+                }
+                ClientEvent::ResponseEnd => {
+                    if let Some(response) = response.take() {
+                        // Anything but waiting for the next request means the
+                        // connection is closing or has switched protocols.
+                        let reusable = matches!(self.conn.next_event(), Ok(ClientEvent::Paused));
+                        return Ok((response, reusable));
+                    }
+                }
+                ClientEvent::NeedMoreData => {
+                    let size = match self.stream.read(&mut buf) {
+                        Ok(size) => size,
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(err.into()),
+                    };
+                    *received = *received || size > 0;
+                    self.conn.receive(&buf[..size]);
+                }
+                ClientEvent::Paused | ClientEvent::Close | ClientEvent::Upgrade(_) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed before the response").into());
+                }
+            }
+        }
+    }
+}
+
+/// A blocking client keeping connections open for reuse.
+///
+/// After a response, the connection is kept for the next request to the same
+/// host and port, unless either side asked to close it. If the server has
+/// closed a kept connection by the time it's reused, so that nothing is read
+/// from it, idempotent requests (`GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and
+/// `TRACE`) are sent again on a new connection; others fail with the error.
+///
+/// Redirects are returned as responses unless `follow_redirects` is set.
+/// Requests default to a 30 second timeout for connecting and for each read
+/// and write.
+#[derive(Debug)]
+pub struct Client {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    idle: HashMap<(String, u16), Connection>,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    /// Creates a client with the default timeout that doesn't follow redirects.
+    pub fn new() -> Client {
+        Client {
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 0,
+            idle: HashMap::new(),
+        }
+    }
+
+    /// Sets the timeout for connecting and for each read and write, or `None` to wait forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Follows up to `max_redirects` redirects for each request.
+    ///
+    /// After a `303`, or a `301` or `302` to a `POST`, the request is repeated
+    /// as a `GET` without a body, as browsers do. `Authorization` and `Cookie`
+    /// headers are dropped when redirected to another host.
+    pub fn follow_redirects(mut self, max_redirects: usize) -> Client {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sends a `GET` request to `url`.
+    pub fn get(&mut self, url: &str) -> Result<Response, ClientError> {
+        self.send(Request::new("GET", url))
+    }
+
+    /// Sends `request`, following redirects if enabled.
+    pub fn send(&mut self, request: Request) -> Result<Response, ClientError> {
+        let Request { mut method, url, mut headers, mut body } = request;
+        let mut target = Target::parse(&url)?;
+        let mut redirects = 0;
+
+        loop {
+            let response = self.send_once(&method, &target, &headers, &body)?;
+
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 if self.max_redirects > 0 => response.headers.get("Location"),
+                _ => None,
+            };
+            let location = match location.and_then(|location| str::from_utf8(location).ok()) {
+                Some(location) => location,
+                None => return Ok(response),
+            };
+
+            if redirects == self.max_redirects {
+                return Err(ClientError::TooManyRedirects);
+            }
+            redirects += 1;
+
+            let next = target.join(location)?;
+            if (next.host.as_str(), next.port) != (target.host.as_str(), target.port) {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("authorization") && !name.eq_ignore_ascii_case("cookie"));
+            }
+            if (response.status == 303 && method != "HEAD") || (matches!(response.status, 301 | 302) && method == "POST") {
+                method = "GET".to_owned();
+                body.clear();
+            }
+            target = next;
+        }
+    }
+
+    fn send_once(&mut self, method: &str, target: &Target, headers: &[(String, Vec<u8>)], body: &[u8]) -> Result<Response, ClientError> {
+        let key = (target.host.clone(), target.port);
+        let mut received = false;
+
+        if let Some(mut connection) = self.idle.remove(&key) {
+            match connection.exchange(method, target, headers, body, &mut received) {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.idle.insert(key, connection);
+                    }
+                    return Ok(response);
+                }
+                // The server closed the connection before the request arrived,
+                // though it may still have acted on it, so only idempotent
+                // requests are repeated.
+                Err(_) if !received && is_idempotent(method) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut connection = Connection::open(target, self.timeout)?;
+        let (response, reusable) = connection.exchange(method, target, headers, body, &mut received)?;
+        if reusable {
+            self.idle.insert(key, connection);
+        }
+        Ok(response)
+    }
+}
+
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use super::{Client, ClientError, Request, Target};
+    use {ServerConnection, ServerEvent};
+
+    /// Serves a connection for each list of responses, answering each request
+    /// with the next response and closing the connection after the last one.
+    /// Returns the requests read on each connection.
+    fn serve(connections: Vec<Vec<&'static str>>) -> (SocketAddr, JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let thread = thread::spawn(move || {
+            connections.into_iter().map(|responses| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut conn = ServerConnection::new();
+                let mut requests = Vec::new();
+                let mut buf = [0; 1024];
+
+                for response in responses {
+                    let mut request = String::new();
+                    loop {
+                        match conn.next_event().unwrap() {
+                            ServerEvent::RequestHead(head) => {
+                                assert!(head.headers.host().is_some());
+                                request = format!("{} {} ", head.method, String::from_utf8(head.url).unwrap());
+                            }
+                            ServerEvent::Body(data) => request.push_str(&String::from_utf8(data).unwrap()),
+                            ServerEvent::RequestEnd => break,
+                            ServerEvent::NeedMoreData => {
+                                let size = stream.read(&mut buf).unwrap();
+                                conn.receive(&buf[..size]);
+                            }
+                            event => panic!("unexpected {:?}", event),
+                        }
+                    }
+
+                    requests.push(request);
+                    stream.write_all(response.as_bytes()).unwrap();
+                    conn.start_response(200, true).unwrap();
+                    conn.end_response().unwrap();
+                }
+
+                requests
+            }).collect()
+        });
+
+        (addr, thread)
+    }
+
+    fn client() -> Client {
+        Client::new().timeout(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_bodies_and_connection_reuse() {
+        let (addr, server) = serve(vec![
+            vec![
+                "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst",
+                "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nsec\r\n3\r\nond\r\n0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n\r\nuntil closed",
+            ],
+            vec!["HTTP/1.1 204 No Content\r\n\r\n"],
+        ]);
+        let mut client = client();
+        let url = format!("http://{}/a?x=1", addr);
+
+        let response = client.get(&url).unwrap();
+        assert_eq!((response.status, &response.body[..]), (200, &b"first"[..]));
+        assert_eq!(response.url, url);
+
+        let response = client.send(Request::new("POST", &url).body("data")).unwrap();
+        assert_eq!((response.status, &response.body[..]), (201, &b"second"[..]));
+        assert_eq!(response.headers.get("Transfer-Encoding"), Some(&b"chunked"[..]));
+
+        let response = client.send(Request::new("PUT", &url)).unwrap();
+        assert_eq!(response.body, b"until closed");
+
+        // A body read until the end closes the connection.
+        assert_eq!(client.get(&url).unwrap().status, 204);
+
+        assert_eq!(server.join().unwrap(), vec![vec!["GET /a?x=1 ", "POST /a?x=1 data", "PUT /a?x=1 "], vec!["GET /a?x=1 "]]);
+    }
+
+    #[test]
+    fn test_closed_idle_connection_is_replaced() {
+        let (addr, server) = serve(vec![
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"],
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"],
+        ]);
+        let mut client = client();
+        let url = format!("http://{}/", addr);
+
+        assert_eq!(client.get(&url).unwrap().body, b"");
+        // Gives the server time to close the first connection.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(client.get(&url).unwrap().body, b"ok");
+
+        assert_eq!(server.join().unwrap(), vec![vec!["GET / "], vec!["GET / "]]);
+    }
+
+    #[test]
+    fn test_post_is_not_replayed() {
+        let (addr, server) = serve(vec![
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"],
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"],
+        ]);
+        let mut client = client();
+        let url = format!("http://{}/", addr);
+
+        assert_eq!(client.get(&url).unwrap().body, b"");
+        thread::sleep(Duration::from_millis(50));
+        assert!(client.send(Request::new("POST", &url).body("x")).is_err());
+        assert_eq!(client.get(&url).unwrap().body, b"ok");
+
+        assert_eq!(server.join().unwrap(), vec![vec!["GET / "], vec!["GET / "]]);
+    }
+
+    #[test]
+    fn test_join() {
+        let base = Target::parse("http://example.com/a/b?x=1").unwrap();
+        let join = |location| base.join(location).unwrap().url();
+
+        assert_eq!(join("?page=2"), "http://example.com/a/b?page=2");
+        assert_eq!(join("./x"), "http://example.com/a/x");
+        assert_eq!(join("../x"), "http://example.com/x");
+        assert_eq!(join("../../../x?y"), "http://example.com/x?y");
+        assert_eq!(join("c/"), "http://example.com/a/c/");
+        assert_eq!(join("/c/./d/../e"), "http://example.com/c/e");
+        assert_eq!(join("#f"), "http://example.com/a/b?x=1");
+        assert_eq!(join("//other:8080/y"), "http://other:8080/y");
+        assert_eq!(join("http://other/y/.."), "http://other/");
+    }
+
+    #[test]
+    fn test_redirects() {
+        let redirect = "HTTP/1.1 303 See Other\r\nLocation: b/c\r\nContent-Length: 0\r\n\r\n";
+        let (addr, server) = serve(vec![
+            vec![redirect],
+            vec![redirect, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"],
+        ]);
+
+        let url = format!("http://{}/a/", addr);
+        assert_eq!(client().get(&url).unwrap().status, 303);
+
+        let response = client().follow_redirects(1).send(Request::new("POST", &url).body("x")).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.url, format!("http://{}/a/b/c", addr));
+
+        assert_eq!(server.join().unwrap(), vec![vec!["GET /a/ "], vec!["POST /a/ x", "GET /a/b/c "]]);
+    }
+
+    #[test]
+    fn test_too_many_redirects() {
+        let redirect = "HTTP/1.1 307 Temporary Redirect\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n";
+        let (addr, server) = serve(vec![vec![redirect, redirect, redirect]]);
+
+        let result = client().follow_redirects(2).send(Request::new("PUT", &format!("http://{}/", addr)).body("x"));
+        assert!(matches!(result, Err(ClientError::TooManyRedirects)));
+        assert_eq!(server.join().unwrap(), vec![vec!["PUT / x", "PUT /loop x", "PUT /loop x"]]);
+    }
+
+    #[test]
+    fn test_invalid_urls() {
+        assert!(matches!(client().get("https://example.com/"), Err(ClientError::UnsupportedScheme)));
+        assert!(matches!(client().get("not a url"), Err(ClientError::InvalidUrl)));
+    }
+}
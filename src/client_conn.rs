@@ -0,0 +1,581 @@
+//! A sans-I/O state machine for the client side of a connection.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use error::{ConnectionError, HttpError};
+use headers::{self, Headers};
+use writer;
+use {Parser, ParserHandler};
+
+/// The head of a final response read by a `ClientConnection`.
+#[derive(Debug, Clone)]
+pub struct ResponseHead {
+    /// The status code.
+    pub status: u16,
+    /// The reason phrase, as sent by the server.
+    pub reason: Vec<u8>,
+    /// The HTTP version as `(major, minor)`.
+    pub version: (u16, u16),
+    /// The response headers.
+    pub headers: Headers,
+    /// `false` if the server is going to close the connection after this response.
+    pub keep_alive: bool,
+}
+
+/// An event returned by `ClientConnection::next_event`.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// An interim `1xx` response, such as `100 Continue` or `103 Early Hints`.
+    Informational { status: u16, headers: Headers },
+    /// The head of the final response to the oldest request sent.
+    ResponseHead(ResponseHead),
+    /// A piece of the response body.
+    Body(Vec<u8>),
+    /// The response is complete.
+    ResponseEnd,
+    /// All received data has been read; pass more to `receive`.
+    NeedMoreData,
+    /// There are no requests waiting for a response.
+    Paused,
+    /// The connection should be closed. Requests that haven't been answered
+    /// yet may be retried on a new connection if they're idempotent.
+    Close,
+    /// The connection has switched to another protocol, after a `101` response
+    /// or a successful `CONNECT`. Holds the data received after the response,
+    /// which belongs to the new protocol.
+    Upgrade(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestKind {
+    Head,
+    Connect,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFraming {
+    Length(u64),
+    Chunked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseState {
+    Idle,
+    Reading,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Open,
+    Switched,
+    Closed,
+}
+
+/// Turns parser callbacks into events for the oldest request waiting for a
+/// response, pausing the parser after each final response.
+#[derive(Debug)]
+struct Collector {
+    events: VecDeque<ClientEvent>,
+    requests: VecDeque<RequestKind>,
+    reason: Vec<u8>,
+    headers: Headers,
+    response: ResponseState,
+    interim: bool,
+    keep_alive: bool,
+    upgrade: bool,
+}
+
+impl ParserHandler for Collector {
+    fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+        self.response = ResponseState::Reading;
+        self.reason.clear();
+        self.headers.clear();
+        true
+    }
+
+    fn on_status(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.reason.extend_from_slice(data);
+        true
+    }
+
+    fn on_header_field(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.headers.on_header_field(data);
+        true
+    }
+
+    fn on_header_value(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.headers.on_header_value(data);
+        true
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.on_headers_complete();
+
+        let status = parser.status_code();
+        self.interim = status / 100 == 1 && status != 101;
+        if self.interim {
+            self.events.push_back(ClientEvent::Informational { status, headers: mem::take(&mut self.headers) });
+            return true;
+        }
+
+        let request = self.requests.front().cloned().unwrap_or(RequestKind::Other);
+        let tunnel = request == RequestKind::Connect && status / 100 == 2;
+        if request == RequestKind::Head || tunnel {
+            parser.skip_body();
+        }
+        self.upgrade = tunnel;
+
+        self.events.push_back(ClientEvent::ResponseHead(ResponseHead {
+            status,
+            reason: mem::take(&mut self.reason),
+            version: parser.http_version(),
+            headers: mem::take(&mut self.headers),
+            keep_alive: parser.should_keep_alive(),
+        }));
+        true
+    }
+
+    fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.events.push_back(ClientEvent::Body(data.to_vec()));
+        true
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        if self.interim {
+            self.response = ResponseState::Idle;
+            return true;
+        }
+
+        self.events.push_back(ClientEvent::ResponseEnd);
+        self.requests.pop_front();
+        self.response = ResponseState::Done;
+        self.keep_alive = parser.should_keep_alive();
+        self.upgrade = self.upgrade || parser.is_upgrade();
+        parser.pause();
+        true
+    }
+}
+
+/// The client side of an HTTP/1.1 connection, without any I/O.
+///
+/// Requests are serialized with `send_request`, `send_data` and
+/// `end_request`, which return the bytes to write to the socket. Data read
+/// from the socket is passed to `receive`, and `next_event` turns it into
+/// events for one response at a time, in the order the requests were sent.
+///
+/// Since the connection knows which request a response answers, it reads
+/// responses to `HEAD` and successful responses to `CONNECT` without a body,
+/// which `Parser::response` can't do on its own. Interim `1xx` responses are
+/// returned as `Informational` events before the final response. A response
+/// without a length is read until the server closes the connection, which is
+/// signalled by passing empty data to `receive`.
+///
+/// Requests may be pipelined: a new one can be sent once the previous one has
+/// been ended, unless the connection is going to be closed.
+///
+/// # Example
+/// ```
+/// use http_muncher::{ClientConnection, ClientEvent};
+///
+/// let mut conn = ClientConnection::new();
+/// let request = conn.send_request("HEAD", "/", [("Host", "example.com")]).unwrap();
+/// assert_eq!(request, b"HEAD / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+/// conn.end_request().unwrap();
+///
+/// conn.receive(b"HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\n");
+///
+/// match conn.next_event().unwrap() {
+///     ClientEvent::ResponseHead(head) => assert_eq!(head.status, 200),
+///     event => panic!("unexpected {:?}", event),
+/// }
+/// assert!(matches!(conn.next_event().unwrap(), ClientEvent::ResponseEnd));
+/// assert!(matches!(conn.next_event().unwrap(), ClientEvent::Paused));
+/// ```
+#[derive(Debug)]
+pub struct ClientConnection {
+    parser: Parser,
+    handler: Collector,
+    buffer: Vec<u8>,
+    eof: bool,
+    request: Option<BodyFraming>,
+    closing: bool,
+    state: State,
+}
+
+impl Default for ClientConnection {
+    fn default() -> ClientConnection {
+        ClientConnection::new()
+    }
+}
+
+impl ClientConnection {
+    /// Creates the state machine for a new connection, before any request has been sent.
+    pub fn new() -> ClientConnection {
+        ClientConnection {
+            parser: Parser::response(),
+            handler: Collector {
+                events: VecDeque::new(),
+                requests: VecDeque::new(),
+                reason: Vec::new(),
+                headers: Headers::new(),
+                response: ResponseState::Idle,
+                interim: false,
+                keep_alive: true,
+                upgrade: false,
+            },
+            buffer: Vec::new(),
+            eof: false,
+            request: None,
+            closing: false,
+            state: State::Open,
+        }
+    }
+
+    /// Starts a request and returns its serialized head.
+    ///
+    /// The body is framed by the `Content-Length` or `Transfer-Encoding:
+    /// chunked` header among `headers`; without either the request has no
+    /// body. Either way the request has to be finished with `end_request`.
+    pub fn send_request<I, N, V>(&mut self, method: &str, target: &str, headers: I) -> Result<Vec<u8>, ConnectionError>
+        where I: IntoIterator<Item = (N, V)>,
+              N: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        if self.state != State::Open || self.closing {
+            return Err(ConnectionError::State("the connection is closing"));
+        }
+        if self.request.is_some() {
+            return Err(ConnectionError::State("the previous request hasn't been ended"));
+        }
+
+        let headers: Vec<(N, V)> = headers.into_iter().collect();
+        let mut framing = BodyFraming::Length(0);
+        let mut closing = false;
+
+        for (name, value) in &headers {
+            let (name, value) = (name.as_ref(), value.as_ref());
+
+            if name.eq_ignore_ascii_case(b"content-length") {
+                let length = headers::content_length(value)
+                    .map_err(|_| ConnectionError::State("invalid Content-Length header"))?;
+                framing = BodyFraming::Length(length);
+            } else if name.eq_ignore_ascii_case(b"transfer-encoding") {
+                let codings = headers::transfer_encoding(value)
+                    .map_err(|_| ConnectionError::State("invalid Transfer-Encoding header"))?;
+                if !codings.last().is_some_and(|coding| coding.eq_ignore_ascii_case(b"chunked")) {
+                    return Err(ConnectionError::State("a request body has to be chunked last"));
+                }
+                framing = BodyFraming::Chunked;
+            } else if name.eq_ignore_ascii_case(b"connection") {
+                closing = closing || headers::connection(value)
+                    .is_ok_and(|options| options.iter().any(|option| option.eq_ignore_ascii_case(b"close")));
+            }
+        }
+
+        let mut head = Vec::new();
+        writer::write_request_head(&mut head, method, target, headers.iter().map(|(name, value)| (name.as_ref(), value.as_ref())))
+            .map_err(|_| ConnectionError::State("invalid request head"))?;
+
+        let kind = if method == "HEAD" {
+            RequestKind::Head
+        } else if method == "CONNECT" {
+            RequestKind::Connect
+        } else {
+            RequestKind::Other
+        };
+
+        self.handler.requests.push_back(kind);
+        self.request = Some(framing);
+        self.closing = closing;
+        Ok(head)
+    }
+
+    /// Returns `data` serialized as a piece of the current request's body.
+    pub fn send_data(&mut self, data: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let mut out = Vec::new();
+
+        match self.request {
+            None => return Err(ConnectionError::State("no request has been started")),
+            Some(BodyFraming::Length(remaining)) => {
+                if data.len() as u64 > remaining {
+                    return Err(ConnectionError::State("the body is longer than its Content-Length"));
+                }
+                self.request = Some(BodyFraming::Length(remaining - data.len() as u64));
+                out.extend_from_slice(data);
+            }
+            Some(BodyFraming::Chunked) => {
+                // Writing to a `Vec` can't fail.
+                let _ = writer::write_chunk(&mut out, data);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Ends the current request, returning the last chunk if the body is chunked.
+    pub fn end_request(&mut self) -> Result<Vec<u8>, ConnectionError> {
+        let mut out = Vec::new();
+
+        match self.request {
+            None => return Err(ConnectionError::State("no request has been started")),
+            Some(BodyFraming::Length(0)) => {}
+            Some(BodyFraming::Length(_)) => {
+                return Err(ConnectionError::State("the body is shorter than its Content-Length"));
+            }
+            Some(BodyFraming::Chunked) => {
+                let _ = writer::write_last_chunk(&mut out);
+            }
+        }
+
+        self.request = None;
+        Ok(out)
+    }
+
+    /// Adds data received from the server. Empty `data` means that the server
+    /// has closed the connection.
+    pub fn receive(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.eof = true;
+        } else {
+            self.buffer.extend_from_slice(data);
+        }
+    }
+
+    /// Returns the next event.
+    ///
+    /// A parsing error, including a connection closed in the middle of a
+    /// response, closes the connection.
+    pub fn next_event(&mut self) -> Result<ClientEvent, ConnectionError> {
+        loop {
+            if let Some(event) = self.handler.events.pop_front() {
+                return Ok(event);
+            }
+
+            match self.state {
+                State::Closed => return Ok(ClientEvent::Close),
+                State::Switched => return Ok(ClientEvent::Paused),
+                State::Open => {}
+            }
+
+            if self.handler.response == ResponseState::Done {
+                if self.handler.upgrade {
+                    self.state = State::Switched;
+                    return Ok(ClientEvent::Upgrade(mem::take(&mut self.buffer)));
+                }
+                if !self.handler.keep_alive || (self.closing && self.handler.requests.is_empty()) {
+                    self.state = State::Closed;
+                    continue;
+                }
+
+                self.handler.response = ResponseState::Idle;
+                self.parser.unpause();
+                continue;
+            }
+
+            if self.handler.response == ResponseState::Idle && self.handler.requests.is_empty() {
+                if self.eof {
+                    self.state = State::Closed;
+                    continue;
+                }
+                return Ok(ClientEvent::Paused);
+            }
+
+            if self.buffer.is_empty() {
+                if !self.eof {
+                    return Ok(ClientEvent::NeedMoreData);
+                }
+
+                // Ends a body read until EOF, or reports a truncated response.
+                if let Err(err) = self.parser.finish(&mut self.handler) {
+                    self.state = State::Closed;
+                    return Err(err.into());
+                }
+                if self.handler.response != ResponseState::Done {
+                    self.state = State::Closed;
+                }
+                continue;
+            }
+
+            self.parse()?;
+        }
+    }
+
+    /// Checks if another request can be sent on this connection.
+    pub fn keep_alive(&self) -> bool {
+        self.state == State::Open && !self.closing && !self.eof && self.handler.keep_alive
+    }
+
+    /// Returns the parser reading the responses.
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    fn parse(&mut self) -> Result<(), ConnectionError> {
+        let size = self.parser.parse(&mut self.handler, &self.buffer);
+        self.buffer.drain(..size);
+
+        if self.parser.has_error() && !self.parser.is_paused() {
+            self.state = State::Closed;
+            return Err(ConnectionError::Http(HttpError::new(self.parser.http_errnum())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientConnection, ClientEvent};
+    use error::ConnectionError;
+
+    fn describe(event: &ClientEvent) -> String {
+        match *event {
+            ClientEvent::Informational { status, .. } => format!("informational {}", status),
+            ClientEvent::ResponseHead(ref head) => format!("head {}", head.status),
+            ClientEvent::Body(ref data) => format!("body {}", String::from_utf8_lossy(data)),
+            ClientEvent::ResponseEnd => "end".to_owned(),
+            ClientEvent::NeedMoreData => "need more data".to_owned(),
+            ClientEvent::Paused => "paused".to_owned(),
+            ClientEvent::Close => "close".to_owned(),
+            ClientEvent::Upgrade(ref data) => format!("upgrade {}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Returns events up to the first one that needs an action from the application.
+    fn events(conn: &mut ClientConnection) -> Vec<String> {
+        let mut events = Vec::new();
+
+        loop {
+            let event = conn.next_event().unwrap();
+            events.push(describe(&event));
+
+            if matches!(event, ClientEvent::NeedMoreData | ClientEvent::Paused |
+                               ClientEvent::Close | ClientEvent::Upgrade(_)) {
+                return events;
+            }
+        }
+    }
+
+    fn send(conn: &mut ClientConnection, method: &str) {
+        conn.send_request(method, "/", [("Host", "example.com")]).unwrap();
+        conn.end_request().unwrap();
+    }
+
+    #[test]
+    fn test_request_bodies() {
+        let mut conn = ClientConnection::new();
+
+        let head = conn.send_request("POST", "/upload", [("Content-Length", "5")]).unwrap();
+        assert_eq!(head, b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+        assert_eq!(conn.send_request("GET", "/", [("Host", "a")]).unwrap_err(),
+                   ConnectionError::State("the previous request hasn't been ended"));
+        assert_eq!(conn.send_data(b"abc").unwrap(), b"abc");
+        assert!(conn.send_data(b"def").is_err());
+        assert!(conn.end_request().is_err());
+        assert_eq!(conn.send_data(b"de").unwrap(), b"de");
+        assert_eq!(conn.end_request().unwrap(), b"");
+
+        conn.send_request("POST", "/", [("Transfer-Encoding", "chunked")]).unwrap();
+        assert_eq!(conn.send_data(b"hello").unwrap(), b"5\r\nhello\r\n");
+        assert_eq!(conn.end_request().unwrap(), b"0\r\n\r\n");
+
+        assert!(conn.send_request("POST", "/", [("Transfer-Encoding", "chunked, gzip")]).is_err());
+        assert!(conn.send_data(b"").is_err());
+    }
+
+    #[test]
+    fn test_pipelined_responses_split_between_buffers() {
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+                     HTTP/1.1 100 Continue\r\n\r\n\
+                     HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+
+        for split in 1..data.len() {
+            let mut conn = ClientConnection::new();
+            send(&mut conn, "HEAD");
+            send(&mut conn, "POST");
+
+            let mut log = Vec::new();
+            for chunk in &[&data[..split], &data[split..]] {
+                conn.receive(chunk);
+                log.extend(events(&mut conn));
+            }
+
+            log.retain(|event| event != "need more data");
+            assert_eq!(log, vec!["head 200", "end", "informational 100", "head 201", "body abc", "end", "paused"],
+                       "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_body_until_eof() {
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "GET");
+
+        conn.receive(b"HTTP/1.1 200 OK\r\n\r\nsome data");
+        assert_eq!(events(&mut conn), vec!["head 200", "body some data", "need more data"]);
+
+        conn.receive(b"");
+        assert_eq!(events(&mut conn), vec!["end", "close"]);
+        assert!(!conn.keep_alive());
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let mut conn = ClientConnection::new();
+        conn.send_request("GET", "/", [("Connection", "close")]).unwrap();
+        conn.end_request().unwrap();
+        assert!(!conn.keep_alive());
+        assert!(conn.send_request("GET", "/", [("Host", "a")]).is_err());
+
+        conn.receive(b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(events(&mut conn), vec!["head 204", "end", "close"]);
+
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "GET");
+        conn.receive(b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(events(&mut conn), vec!["head 200", "end", "close"]);
+
+        // The server closing before responding.
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "GET");
+        conn.receive(b"");
+        assert_eq!(events(&mut conn), vec!["close"]);
+    }
+
+    #[test]
+    fn test_truncated_response() {
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "GET");
+        conn.receive(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n12345");
+        conn.receive(b"");
+
+        assert_eq!(describe(&conn.next_event().unwrap()), "head 200");
+        assert_eq!(describe(&conn.next_event().unwrap()), "body 12345");
+        match conn.next_event() {
+            Err(ConnectionError::Http(err)) => assert_eq!(err.name(), "HPE_INVALID_EOF_STATE"),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(matches!(conn.next_event(), Ok(ClientEvent::Close)));
+    }
+
+    #[test]
+    fn test_upgrade_and_connect() {
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "GET");
+        conn.receive(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nframes");
+        assert_eq!(events(&mut conn), vec!["head 101", "end", "upgrade frames"]);
+        assert_eq!(events(&mut conn), vec!["paused"]);
+
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "CONNECT");
+        conn.receive(b"HTTP/1.1 200 Connection Established\r\nContent-Length: 100\r\n\r\ntunnel");
+        assert_eq!(events(&mut conn), vec!["head 200", "end", "upgrade tunnel"]);
+
+        let mut conn = ClientConnection::new();
+        send(&mut conn, "CONNECT");
+        conn.receive(b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 4\r\n\r\ndeny");
+        assert_eq!(events(&mut conn), vec!["head 407", "body deny", "end", "paused"]);
+    }
+}
@@ -0,0 +1,427 @@
+//! Transparent decoding of compressed message bodies.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+use brotli_decompressor::DecompressorWriter;
+use flate2::write::{GzDecoder, ZlibDecoder};
+
+use adapter::HeaderWatch;
+use headers::trim;
+use {Parser, ParserHandler};
+
+/// Output size up to which the compression ratio isn't checked, so that short
+/// bodies with a legitimately high ratio aren't rejected.
+const RATIO_THRESHOLD: u64 = 1 << 20;
+
+/// Size of the brotli decoder's output buffer.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// An error that stopped the parser while decoding a body.
+#[derive(Debug)]
+pub enum DecompressError {
+    /// The body isn't valid data in its content coding.
+    Corrupt(io::Error),
+    /// The decoded body exceeds the size or compression ratio limits.
+    TooLarge,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecompressError::Corrupt(ref err) => write!(f, "corrupt compressed body: {}", err),
+            DecompressError::TooLarge => f.write_str("decompressed body exceeds the limits"),
+        }
+    }
+}
+
+impl Error for DecompressError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DecompressError::Corrupt(ref err) => Some(err),
+            DecompressError::TooLarge => None,
+        }
+    }
+}
+
+/// Buffers decoded output, refusing to grow past `limit` bytes in total.
+struct Sink {
+    buf: Vec<u8>,
+    written: u64,
+    limit: u64,
+    exceeded: bool,
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.written + data.len() as u64 > self.limit {
+            self.exceeded = true;
+            return Err(io::Error::other("decompressed body is too large"));
+        }
+
+        self.written += data.len() as u64;
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Decoder {
+    Gzip(GzDecoder<Sink>),
+    Deflate(ZlibDecoder<Sink>),
+    Brotli(Box<DecompressorWriter<Sink>>),
+}
+
+impl Decoder {
+    /// Picks a decoder for a `Content-Encoding` value. Only a single coding is
+    /// supported; anything else is passed through as is.
+    fn for_encoding(value: &[u8]) -> Option<Decoder> {
+        let mut codings = value.split(|&b| b == b',')
+            .map(trim)
+            .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case(b"identity"));
+
+        let coding = match (codings.next(), codings.next()) {
+            (Some(coding), None) => coding.to_ascii_lowercase(),
+            _ => return None,
+        };
+
+        let sink = Sink {
+            buf: Vec::new(),
+            written: 0,
+            limit: 0,
+            exceeded: false,
+        };
+
+        match &coding[..] {
+            b"gzip" | b"x-gzip" => Some(Decoder::Gzip(GzDecoder::new(sink))),
+            b"deflate" => Some(Decoder::Deflate(ZlibDecoder::new(sink))),
+            b"br" => Some(Decoder::Brotli(Box::new(DecompressorWriter::new(sink, BROTLI_BUFFER_SIZE)))),
+            _ => None,
+        }
+    }
+
+    fn sink(&mut self) -> &mut Sink {
+        match *self {
+            Decoder::Gzip(ref mut decoder) => decoder.get_mut(),
+            Decoder::Deflate(ref mut decoder) => decoder.get_mut(),
+            Decoder::Brotli(ref mut decoder) => decoder.get_mut(),
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let written = match *self {
+                Decoder::Gzip(ref mut decoder) => decoder.write(data)?,
+                Decoder::Deflate(ref mut decoder) => decoder.write(data)?,
+                Decoder::Brotli(ref mut decoder) => decoder.write(data)?,
+            };
+
+            // The compressed stream has ended; ignore whatever follows it.
+            if written == 0 {
+                break;
+            }
+            data = &data[written..];
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match *self {
+            Decoder::Gzip(ref mut decoder) => decoder.try_finish(),
+            Decoder::Deflate(ref mut decoder) => decoder.try_finish(),
+            Decoder::Brotli(ref mut decoder) => decoder.close(),
+        }
+    }
+}
+
+/// A handler adapter decoding `gzip`, `deflate` and `br` message bodies.
+///
+/// The `Content-Encoding` header is read while headers are passed on to the
+/// inner handler unchanged, and body chunks are decoded as they arrive before
+/// they're passed to the inner handler's `on_body`. Bodies with other or
+/// several stacked content codings are passed through as is.
+///
+/// Decoded output is bounded: decoding fails with `DecompressError::TooLarge`
+/// once the body grows above `max_size` bytes, or above `max_ratio` times the
+/// size of the compressed data received so far (checked past the first MiB).
+/// On errors the parser is stopped, and the error is available from `error`.
+///
+/// # Example
+/// ```
+/// use http_muncher::{Decompress, Parser, ParserHandler};
+///
+/// struct Body(Vec<u8>);
+///
+/// impl ParserHandler for Body {
+///     fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+///         self.0.extend_from_slice(data);
+///         true
+///     }
+/// }
+///
+/// let mut handler = Decompress::new(Body(Vec::new())).max_size(10 * 1024 * 1024);
+/// let mut parser = Parser::response();
+/// parser.parse(&mut handler, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nplain");
+///
+/// assert_eq!(handler.inner().0, b"plain");
+/// ```
+pub struct Decompress<H> {
+    inner: H,
+    headers: HeaderWatch,
+    decoder: Option<Decoder>,
+    max_ratio: u64,
+    max_size: u64,
+    received: u64,
+    error: Option<DecompressError>,
+}
+
+impl<H: ParserHandler> Decompress<H> {
+    /// Wraps `inner`, limiting the compression ratio to 100 and leaving the size unlimited.
+    pub fn new(inner: H) -> Decompress<H> {
+        Decompress {
+            inner,
+            headers: HeaderWatch::new("Content-Encoding"),
+            decoder: None,
+            max_ratio: 100,
+            max_size: u64::MAX,
+            received: 0,
+            error: None,
+        }
+    }
+
+    /// Sets the maximum ratio of decoded to compressed body size.
+    pub fn max_ratio(mut self, max_ratio: u64) -> Decompress<H> {
+        self.max_ratio = max_ratio;
+        self
+    }
+
+    /// Sets the maximum size of a decoded body in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Decompress<H> {
+        self.max_size = max_size;
+        self
+    }
+
+    inner_accessors!(H);
+
+    /// Returns the error that stopped the parser, if decoding has failed.
+    pub fn error(&self) -> Option<&DecompressError> {
+        self.error.as_ref()
+    }
+
+    /// Passes decoded output on to the inner handler, or stores the decoding error.
+    fn deliver(&mut self, parser: &mut Parser, result: io::Result<()>) -> bool {
+        let sink = match self.decoder {
+            Some(ref mut decoder) => decoder.sink(),
+            None => return true,
+        };
+
+        if let Err(err) = result {
+            self.error = Some(if sink.exceeded {
+                DecompressError::TooLarge
+            } else {
+                DecompressError::Corrupt(err)
+            });
+            return false;
+        }
+
+        if sink.buf.is_empty() {
+            return true;
+        }
+
+        let proceed = self.inner.on_body(parser, &sink.buf);
+        sink.buf.clear();
+        proceed
+    }
+}
+
+impl<H: ParserHandler> ParserHandler for Decompress<H> {
+    fn on_message_begin(&mut self, parser: &mut Parser) -> bool {
+        self.headers.clear();
+        self.decoder = None;
+        self.received = 0;
+        self.error = None;
+        self.inner.on_message_begin(parser)
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.finish();
+        self.decoder = Decoder::for_encoding(&self.headers.values().join(&b","[..]));
+        self.inner.on_headers_complete(parser)
+    }
+
+    fn on_body(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.received += data.len() as u64;
+
+        let limit = cmp::min(self.max_size,
+                             cmp::max(self.received.saturating_mul(self.max_ratio), RATIO_THRESHOLD));
+
+        let result = match self.decoder {
+            Some(ref mut decoder) => {
+                decoder.sink().limit = limit;
+                decoder.write(data)
+            }
+            None => return self.inner.on_body(parser, data),
+        };
+
+        self.deliver(parser, result)
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        let result = match self.decoder {
+            // A decoder without any input reports a truncated stream, but a
+            // message without a body, such as a `204`, has nothing to decode.
+            Some(ref mut decoder) if self.received > 0 => decoder.finish(),
+            _ => Ok(()),
+        };
+
+        if !self.deliver(parser, result) {
+            return false;
+        }
+
+        self.decoder = None;
+        self.inner.on_message_complete(parser)
+    }
+
+    pass_through!(on_url, on_status, on_header_field, on_header_value, on_chunk_header, on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+
+    use super::{Decompress, DecompressError};
+    use {Parser, ParserHandler};
+
+    /// "Hello, brotli!" compressed with brotli.
+    const BROTLI_HELLO: &[u8] = &[139, 6, 128, 72, 101, 108, 108, 111, 44, 32, 98, 114, 111, 116, 108,
+                                   105, 33, 3];
+
+    #[derive(Default)]
+    struct Body {
+        data: Vec<u8>,
+        complete: bool,
+    }
+
+    impl ParserHandler for Body {
+        fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+            self.data.extend_from_slice(data);
+            true
+        }
+
+        fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+            self.complete = true;
+            true
+        }
+    }
+
+    fn response(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut res = format!("HTTP/1.1 200 OK\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+                              encoding,
+                              body.len())
+            .into_bytes();
+        res.extend_from_slice(body);
+        res
+    }
+
+    fn parse_split(handler: &mut Decompress<Body>, res: &[u8]) -> Parser {
+        let mut parser = Parser::response();
+        for chunk in res.chunks(7) {
+            parser.parse(handler, chunk);
+        }
+        parser
+    }
+
+    #[test]
+    fn test_gzip_and_deflate() {
+        let text = b"The quick brown fox jumps over the lazy dog".repeat(10);
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&text).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&text).unwrap();
+        let zlib = zlib.finish().unwrap();
+
+        for &(encoding, ref body) in &[("gzip", gzip), ("identity, Deflate", zlib)] {
+            let mut handler = Decompress::new(Body::default());
+            let parser = parse_split(&mut handler, &response(encoding, body));
+
+            assert!(!parser.has_error());
+            assert!(handler.inner().complete);
+            assert_eq!(handler.inner().data, text);
+        }
+    }
+
+    #[test]
+    fn test_brotli() {
+        let mut handler = Decompress::new(Body::default());
+        parse_split(&mut handler, &response("br", BROTLI_HELLO));
+
+        assert_eq!(handler.inner().data, b"Hello, brotli!");
+    }
+
+    #[test]
+    fn test_bodiless_responses() {
+        for res in &[&b"HTTP/1.1 204 No Content\r\nContent-Encoding: gzip\r\n\r\n"[..], &response("br", b"")[..]] {
+            let mut handler = Decompress::new(Body::default());
+            let parser = parse_split(&mut handler, res);
+
+            assert!(!parser.has_error());
+            assert!(handler.error().is_none());
+            assert!(handler.inner().complete);
+            assert!(handler.inner().data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_unsupported_encoding_is_passed_through() {
+        let mut handler = Decompress::new(Body::default());
+        parse_split(&mut handler, &response("gzip, br", b"opaque"));
+
+        assert_eq!(handler.inner().data, b"opaque");
+    }
+
+    #[test]
+    fn test_corrupt_body() {
+        let mut handler = Decompress::new(Body::default());
+        let parser = parse_split(&mut handler, &response("gzip", b"definitely not gzip"));
+
+        assert_eq!(parser.error(), "HPE_CB_body");
+        assert!(matches!(handler.error(), Some(&DecompressError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&vec![0; 16 << 20]).unwrap();
+        let bomb = response("gzip", &gzip.finish().unwrap());
+
+        let mut handler = Decompress::new(Body::default());
+        let mut parser = Parser::response();
+        parser.parse(&mut handler, &bomb);
+
+        assert_eq!(parser.error(), "HPE_CB_body");
+        assert!(matches!(handler.error(), Some(&DecompressError::TooLarge)));
+        assert!(handler.inner().data.len() <= 1 << 20);
+
+        let mut handler = Decompress::new(Body::default()).max_size(100);
+        let mut parser = Parser::response();
+        parser.parse(&mut handler, &response("br", BROTLI_HELLO));
+        assert!(!parser.has_error());
+
+        let mut handler = Decompress::new(Body::default()).max_size(10);
+        parser.reset();
+        parser.parse(&mut handler, &response("br", BROTLI_HELLO));
+        assert_eq!(parser.error(), "HPE_CB_body");
+    }
+}
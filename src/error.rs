@@ -0,0 +1,109 @@
+//! Errors returned by the parser.
+
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+
+use {_http_errno_description, _http_errno_name};
+
+/// An error reported by http-parser, e.g. `HPE_INVALID_METHOD`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HttpError {
+    errno: u8,
+}
+
+impl HttpError {
+    pub(crate) fn new(errno: u8) -> HttpError {
+        HttpError { errno }
+    }
+
+    /// Returns the mnemonic name of the error (`HPE_INVALID_METHOD` and so on).
+    pub fn name(&self) -> &'static str {
+        _http_errno_name(self.errno)
+    }
+
+    /// Returns the description of the error.
+    pub fn description(&self) -> &'static str {
+        _http_errno_description(self.errno)
+    }
+}
+
+impl fmt::Debug for HttpError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} ({})", self.description(), self.name())
+    }
+}
+
+impl Error for HttpError {}
+
+/// An error returned by `Parser::try_parse` and `Parser::finish`.
+///
+/// Without handler errors, as for `finish`, `E` is `Infallible`.
+#[derive(Debug, PartialEq)]
+pub enum ParseError<E = Infallible> {
+    /// The data couldn't be parsed.
+    Http(HttpError),
+    /// A handler callback returned an error.
+    Callback(E),
+}
+
+impl<E: fmt::Display> fmt::Display for ParseError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Http(ref err) => write!(fmt, "HTTP parsing error: {}", err),
+            ParseError::Callback(ref err) => write!(fmt, "handler error: {}", err),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ParseError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ParseError::Http(ref err) => Some(err),
+            ParseError::Callback(ref err) => Some(err),
+        }
+    }
+}
+
+/// An error returned by `ServerConnection` and `ClientConnection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// The peer sent data that couldn't be parsed.
+    Http(HttpError),
+    /// The connection was used in a way its current state doesn't allow,
+    /// e.g. a response was started before a request was read.
+    State(&'static str),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConnectionError::Http(ref err) => write!(fmt, "HTTP parsing error: {}", err),
+            ConnectionError::State(message) => write!(fmt, "invalid connection state: {}", message),
+        }
+    }
+}
+
+impl From<ParseError> for ConnectionError {
+    fn from(err: ParseError) -> ConnectionError {
+        match err {
+            ParseError::Http(err) => ConnectionError::Http(err),
+            ParseError::Callback(never) => match never {},
+        }
+    }
+}
+
+impl Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConnectionError::Http(ref err) => Some(err),
+            ConnectionError::State(_) => None,
+        }
+    }
+}
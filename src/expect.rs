@@ -0,0 +1,221 @@
+//! Server-side handling of `Expect: 100-continue`.
+
+use std::io::{self, Write};
+
+use adapter::HeaderWatch;
+use headers::{self, Expectation};
+use writer;
+use {Parser, ParserHandler};
+
+/// A handler adapter that holds back request bodies until the server decides
+/// whether it wants them.
+///
+/// When a request with `Expect: 100-continue` has been read up to the end of
+/// its headers, the parser is paused right after the inner handler's
+/// `on_headers_complete`, and `expects_continue` returns `true`. The server
+/// then either calls `accept`, which writes `100 Continue` and unpauses the
+/// parser, and continues parsing from the number of bytes returned by `parse`;
+/// or calls `reject`, which writes `417 Expectation Failed`, after which the
+/// connection should be closed.
+///
+/// As required by RFC 9110, the expectation is ignored in HTTP/1.0 requests.
+///
+/// # Example
+/// ```
+/// use http_muncher::{ExpectContinue, Parser, ParserHandler};
+///
+/// struct Body(Vec<u8>);
+///
+/// impl ParserHandler for Body {
+///     fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+///         self.0.extend_from_slice(data);
+///         true
+///     }
+/// }
+///
+/// let request = b"PUT /file HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello";
+/// let mut handler = ExpectContinue::new(Body(Vec::new()));
+/// let mut parser = Parser::request();
+/// let mut output = Vec::new();
+///
+/// let size = parser.parse(&mut handler, request);
+/// assert!(handler.expects_continue());
+/// assert!(handler.inner().0.is_empty());
+///
+/// handler.accept(&mut parser, &mut output).unwrap();
+/// parser.parse(&mut handler, &request[size..]);
+///
+/// assert_eq!(output, b"HTTP/1.1 100 Continue\r\n\r\n");
+/// assert_eq!(handler.inner().0, b"hello");
+/// ```
+#[derive(Debug)]
+pub struct ExpectContinue<H> {
+    inner: H,
+    headers: HeaderWatch,
+    expects_continue: bool,
+}
+
+impl<H: ParserHandler> ExpectContinue<H> {
+    /// Wraps `inner`.
+    pub fn new(inner: H) -> ExpectContinue<H> {
+        ExpectContinue {
+            inner,
+            headers: HeaderWatch::new("Expect"),
+            expects_continue: false,
+        }
+    }
+
+    inner_accessors!(H);
+
+    /// Checks if the parser is paused waiting for `accept` or `reject`.
+    pub fn expects_continue(&self) -> bool {
+        self.expects_continue
+    }
+
+    /// Writes `100 Continue` to `out` and unpauses the parser to read the body.
+    pub fn accept<W: Write>(&mut self, parser: &mut Parser, out: &mut W) -> io::Result<()> {
+        if self.expects_continue {
+            writer::write_continue(out)?;
+            self.expects_continue = false;
+            parser.unpause();
+        }
+        Ok(())
+    }
+
+    /// Writes `417 Expectation Failed` to `out`, leaving the parser paused.
+    pub fn reject<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if self.expects_continue {
+            writer::write_expectation_failed(out)?;
+            self.expects_continue = false;
+        }
+        Ok(())
+    }
+}
+
+impl<H: ParserHandler> ParserHandler for ExpectContinue<H> {
+    fn on_message_begin(&mut self, parser: &mut Parser) -> bool {
+        self.headers.clear();
+        self.expects_continue = false;
+        self.inner.on_message_begin(parser)
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.finish();
+
+        if !self.inner.on_headers_complete(parser) {
+            return false;
+        }
+
+        // Several `Expect` headers are only a `100-continue` expectation if they all are.
+        let values = self.headers.values();
+        let expect = !values.is_empty() &&
+            values.iter().all(|value| headers::expect(value) == Ok(Expectation::Continue));
+
+        if expect && parser.http_version() >= (1, 1) {
+            self.expects_continue = true;
+            parser.pause();
+        }
+        true
+    }
+
+    pass_through!(on_url, on_status, on_header_field, on_header_value, on_body, on_message_complete,
+                  on_chunk_header, on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExpectContinue;
+    use writer::{CONTINUE, EXPECTATION_FAILED};
+    use {Parser, ParserHandler};
+
+    #[derive(Default)]
+    struct Recorder {
+        body: Vec<u8>,
+        complete: bool,
+    }
+
+    impl ParserHandler for Recorder {
+        fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+            self.body.extend_from_slice(data);
+            true
+        }
+
+        fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+            self.complete = true;
+            true
+        }
+    }
+
+    /// Parses `chunks` in turn, accepting the expectation whenever the parser pauses.
+    fn parse_accepting(chunks: &[&[u8]]) -> (ExpectContinue<Recorder>, Vec<u8>) {
+        let mut parser = Parser::request();
+        let mut handler = ExpectContinue::new(Recorder::default());
+        let mut output = Vec::new();
+
+        for &chunk in chunks {
+            let mut chunk = chunk;
+            while !chunk.is_empty() {
+                let size = parser.parse(&mut handler, chunk);
+                chunk = &chunk[size..];
+
+                if parser.is_paused() {
+                    assert!(handler.expects_continue());
+                    assert!(handler.inner().body.is_empty());
+                    handler.accept(&mut parser, &mut output).unwrap();
+                }
+            }
+        }
+
+        assert!(!parser.has_error());
+        (handler, output)
+    }
+
+    #[test]
+    fn test_continue_split_between_buffers() {
+        let data = b"POST /upload HTTP/1.1\r\nHost: example.com\r\nExpect: 100-Continue\r\nContent-Length: 5\r\n\r\nhello";
+
+        for split in 1..data.len() {
+            let (handler, output) = parse_accepting(&[&data[..split], &data[split..]]);
+
+            assert_eq!(output, CONTINUE, "split at {}", split);
+            assert_eq!(handler.inner().body, b"hello", "split at {}", split);
+            assert!(handler.inner().complete);
+            assert!(!handler.expects_continue());
+        }
+    }
+
+    #[test]
+    fn test_no_pause_without_expectation() {
+        let (handler, output) = parse_accepting(&[b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi"]);
+        assert!(output.is_empty());
+        assert_eq!(handler.inner().body, b"hi");
+
+        let (handler, output) = parse_accepting(&[b"POST / HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi"]);
+        assert!(output.is_empty());
+        assert_eq!(handler.inner().body, b"hi");
+
+        let (_, output) = parse_accepting(&[b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nExpect: other\r\nContent-Length: 2\r\n\r\nhi"]);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut parser = Parser::request();
+        let mut handler = ExpectContinue::new(Recorder::default());
+        let mut output = Vec::new();
+
+        parser.parse(&mut handler, b"PUT / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(handler.expects_continue());
+
+        handler.reject(&mut output).unwrap();
+        assert_eq!(output, EXPECTATION_FAILED);
+        assert!(parser.is_paused());
+        assert!(!handler.expects_continue());
+        assert!(handler.inner().body.is_empty());
+
+        // Further calls have nothing left to answer.
+        handler.reject(&mut output).unwrap();
+        handler.accept(&mut parser, &mut output).unwrap();
+        assert_eq!(output, EXPECTATION_FAILED);
+    }
+}
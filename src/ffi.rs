@@ -1,53 +1,63 @@
 //! This module provides an interface to the NodeJS http-parser library.
 
 use libc;
-use std::mem;
+use std::mem::MaybeUninit;
 
-pub type HttpCallback = extern fn(*mut HttpParser) -> libc::c_int;
-pub type HttpDataCallback = extern fn(*mut HttpParser, *const u32, libc::size_t) -> libc::c_int;
+pub type HttpCallback = extern "C" fn(*mut HttpParser) -> libc::c_int;
+pub type HttpDataCallback = extern "C" fn(*mut HttpParser, *const libc::c_char, libc::size_t) -> libc::c_int;
 
+/// Kind of messages a `Parser` handles.
 #[repr(C)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ParserType {
+    /// HTTP requests.
     HttpRequest,
+    /// HTTP responses.
     HttpResponse,
+    /// Both requests and responses, detected from the first message.
     HttpBoth
 }
 
 #[repr(C)]
 pub struct HttpParser {
     // Private Interface
-    _internal_state: libc::uint32_t,
-    _nread: libc::uint32_t,
-    _content_length: libc::uint64_t,
+    _internal_state: u32,
+    _nread: u32,
+    _content_length: u64,
 
     // Read-Only
     pub http_major: libc::c_ushort,
     pub http_minor: libc::c_ushort,
-    pub _extended_status: libc::uint32_t,
+    pub _extended_status: u32,
 
     // Public Interface
     pub data: *mut libc::c_void
 }
 
+// `data` only points to the parser context while `http_parser_execute` runs,
+// and it's reset to null before `Parser::parse` returns.
 unsafe impl Send for HttpParser { }
 
 impl HttpParser {
     pub fn new(parser_type: ParserType) -> HttpParser {
-        let mut p: HttpParser = unsafe { mem::uninitialized() };
-        unsafe { http_parser_init(&mut p as *mut _, parser_type); }
-        return p;
+        let mut p = MaybeUninit::<HttpParser>::uninit();
+        unsafe {
+            // `http_parser_init` doesn't read the struct before clearing it, except for `data`.
+            (*p.as_mut_ptr()).data = ::std::ptr::null_mut();
+            http_parser_init(p.as_mut_ptr(), parser_type);
+            p.assume_init()
+        }
     }
 
     pub fn http_body_is_final(&self) -> libc::c_int {
-        unsafe { return http_body_is_final(self); }
+        unsafe { http_body_is_final(self) }
     }
 
     pub fn http_should_keep_alive(&self) -> libc::c_int {
         unsafe { http_should_keep_alive(self) }
     }
 
-    pub fn http_parser_pause(&self, paused: libc::c_int) {
+    pub fn http_parser_pause(&mut self, paused: libc::c_int) {
         unsafe { http_parser_pause(self, paused) }
     }
 }
@@ -66,12 +76,38 @@ pub struct HttpParserSettings {
     pub on_chunk_complete: HttpCallback
 }
 
+/// Components of a URL, in the order of `HttpParserUrl::field_data`.
+pub const UF_SCHEMA: usize = 0;
+pub const UF_HOST: usize = 1;
+pub const UF_PORT: usize = 2;
+pub const UF_PATH: usize = 3;
+pub const UF_QUERY: usize = 4;
+pub const UF_FRAGMENT: usize = 5;
+pub const UF_USERINFO: usize = 6;
+pub const UF_MAX: usize = 7;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct HttpParserUrlField {
+    pub off: u16,
+    pub len: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct HttpParserUrl {
+    pub field_set: u16,
+    pub port: u16,
+    pub field_data: [HttpParserUrlField; UF_MAX],
+}
+
+#[cfg(not(miri))]
 #[allow(dead_code)]
 extern "C" {
     pub fn http_parser_version() -> u32;
     pub fn http_parser_init(parser: *mut HttpParser, parser_type: ParserType);
     pub fn http_parser_settings_init(settings: *mut HttpParserSettings);
-    pub fn http_parser_execute(parser: *mut HttpParser, settings: *const HttpParserSettings, data: *const u8, len: libc::size_t) -> libc::size_t;
+    pub fn http_parser_execute(parser: *mut HttpParser, settings: *const HttpParserSettings, data: *const libc::c_char, len: libc::size_t) -> libc::size_t;
     pub fn http_method_str(method_code: u8) -> *const libc::c_char;
     pub fn http_errno_name(http_errno: u8) -> *const libc::c_char;
     pub fn http_errno_description(http_errno: u8) -> *const libc::c_char;
@@ -81,5 +117,321 @@ extern "C" {
     pub fn http_get_struct_flags(parser: *const HttpParser) -> u32;
 
     pub fn http_should_keep_alive(parser: *const HttpParser) -> libc::c_int;
-    pub fn http_parser_pause(parser: *const HttpParser, paused: libc::c_int);
+    pub fn http_parser_pause(parser: *mut HttpParser, paused: libc::c_int);
+    pub fn http_parser_parse_url(buf: *const libc::c_char, buflen: libc::size_t, is_connect: libc::c_int, u: *mut HttpParserUrl) -> libc::c_int;
+}
+
+#[cfg(miri)]
+pub use self::mock::*;
+
+/// Miri can't call into C, so under `cfg(miri)` the library is replaced with
+/// a stand-in that drives the callbacks the same way `http_parser_execute`
+/// does: it hands out slices of the input, honours callback results and pausing,
+/// and keeps the error number where `http_get_struct_flags` reports it.
+///
+/// Every non-empty input is treated as a complete message with the first half
+/// of it as the URL and the rest as the body.
+#[cfg(miri)]
+#[allow(dead_code)]
+mod mock {
+    use libc;
+    use super::{HttpParser, HttpParserSettings, HttpParserUrl, ParserType, UF_PATH};
+
+    const HPE_OK: u32 = 0;
+    const HPE_CB_MESSAGE_BEGIN: u32 = 1;
+    const HPE_CB_URL: u32 = 2;
+    const HPE_CB_HEADERS_COMPLETE: u32 = 5;
+    const HPE_CB_BODY: u32 = 6;
+    const HPE_CB_MESSAGE_COMPLETE: u32 = 7;
+    const HPE_PAUSED: u32 = 30;
+
+    static ERRNO_NAMES: [&[u8]; 32] = [
+        b"HPE_OK\0", b"HPE_CB_message_begin\0", b"HPE_CB_url\0", b"HPE_CB_header_field\0",
+        b"HPE_CB_header_value\0", b"HPE_CB_headers_complete\0", b"HPE_CB_body\0",
+        b"HPE_CB_message_complete\0", b"HPE_CB_status\0", b"HPE_CB_chunk_header\0",
+        b"HPE_CB_chunk_complete\0", b"HPE_INVALID_EOF_STATE\0", b"HPE_HEADER_OVERFLOW\0",
+        b"HPE_CLOSED_CONNECTION\0", b"HPE_INVALID_VERSION\0", b"HPE_INVALID_STATUS\0",
+        b"HPE_INVALID_METHOD\0", b"HPE_INVALID_URL\0", b"HPE_INVALID_HOST\0", b"HPE_INVALID_PORT\0",
+        b"HPE_INVALID_PATH\0", b"HPE_INVALID_QUERY_STRING\0", b"HPE_INVALID_FRAGMENT\0",
+        b"HPE_LF_EXPECTED\0", b"HPE_INVALID_HEADER_TOKEN\0", b"HPE_INVALID_CONTENT_LENGTH\0",
+        b"HPE_INVALID_CHUNK_SIZE\0", b"HPE_INVALID_CONSTANT\0", b"HPE_INVALID_INTERNAL_STATE\0",
+        b"HPE_STRICT\0", b"HPE_PAUSED\0", b"HPE_UNKNOWN\0",
+    ];
+
+    fn errno(parser: &HttpParser) -> u32 {
+        (parser._extended_status >> 24) & 0x7F
+    }
+
+    fn set_errno(parser: &mut HttpParser, errno: u32) {
+        parser._extended_status = (parser._extended_status & !(0x7F << 24)) | (errno << 24);
+    }
+
+    pub unsafe fn http_parser_version() -> u32 {
+        (2 << 16) | (7 << 8) | 1
+    }
+
+    pub unsafe fn http_parser_init(parser: *mut HttpParser, _parser_type: ParserType) {
+        let data = (*parser).data;
+        ::std::ptr::write_bytes(parser, 0, 1);
+        (*parser).data = data;
+    }
+
+    pub unsafe fn http_parser_execute(parser: *mut HttpParser,
+                                      settings: *const HttpParserSettings,
+                                      data: *const libc::c_char,
+                                      len: libc::size_t)
+                                      -> libc::size_t {
+        let settings = &*settings;
+
+        if errno(&*parser) != HPE_OK || len == 0 {
+            return 0;
+        }
+
+        macro_rules! notify {
+            ($callback:ident, $errno:expr, $pos:expr) => {
+                if (settings.$callback)(parser) != 0 {
+                    set_errno(&mut *parser, $errno);
+                }
+                if errno(&*parser) != HPE_OK {
+                    return $pos;
+                }
+            }
+        }
+
+        macro_rules! data {
+            ($callback:ident, $errno:expr, $start:expr, $end:expr) => {
+                if (settings.$callback)(parser, data.offset($start as isize), $end - $start) != 0 {
+                    set_errno(&mut *parser, $errno);
+                }
+                if errno(&*parser) != HPE_OK {
+                    return $end;
+                }
+            }
+        }
+
+        let mid = len / 2;
+
+        (*parser).http_major = 1;
+        (*parser).http_minor = 1;
+        (*parser)._extended_status = (1 << 16) | 200;
+
+        notify!(on_message_begin, HPE_CB_MESSAGE_BEGIN, 0);
+        data!(on_url, HPE_CB_URL, 0, mid);
+        // As in http-parser, 1 from `on_headers_complete` means "skip the body".
+        let skip_body = match (settings.on_headers_complete)(parser) {
+            0 => false,
+            1 => true,
+            _ => {
+                set_errno(&mut *parser, HPE_CB_HEADERS_COMPLETE);
+                return mid;
+            }
+        };
+        if errno(&*parser) != HPE_OK {
+            return mid;
+        }
+        if !skip_body {
+            data!(on_body, HPE_CB_BODY, mid, len);
+        }
+        notify!(on_message_complete, HPE_CB_MESSAGE_COMPLETE, len);
+
+        len
+    }
+
+    pub unsafe fn http_method_str(method_code: u8) -> *const libc::c_char {
+        let name: &'static [u8] = if method_code == 1 { b"GET\0" } else { b"<unknown>\0" };
+        name.as_ptr() as *const libc::c_char
+    }
+
+    pub unsafe fn http_errno_name(http_errno: u8) -> *const libc::c_char {
+        ERRNO_NAMES[http_errno as usize & 0x1F].as_ptr() as *const libc::c_char
+    }
+
+    pub unsafe fn http_errno_description(http_errno: u8) -> *const libc::c_char {
+        http_errno_name(http_errno)
+    }
+
+    pub unsafe fn http_body_is_final(_parser: *const HttpParser) -> libc::c_int {
+        0
+    }
+
+    pub unsafe fn http_get_struct_flags(parser: *const HttpParser) -> u32 {
+        (*parser)._extended_status
+    }
+
+    pub unsafe fn http_should_keep_alive(_parser: *const HttpParser) -> libc::c_int {
+        1
+    }
+
+    pub unsafe fn http_parser_pause(parser: *mut HttpParser, paused: libc::c_int) {
+        let current = errno(&*parser);
+        if current == HPE_OK || current == HPE_PAUSED {
+            set_errno(&mut *parser, if paused != 0 { HPE_PAUSED } else { HPE_OK });
+        }
+    }
+
+    /// Treats the whole URL as a path.
+    pub unsafe fn http_parser_parse_url(_buf: *const libc::c_char,
+                                        buflen: libc::size_t,
+                                        _is_connect: libc::c_int,
+                                        u: *mut HttpParserUrl)
+                                        -> libc::c_int {
+        if buflen == 0 || buflen > u16::MAX as usize {
+            return 1;
+        }
+
+        *u = HttpParserUrl::default();
+        (*u).field_set = 1 << UF_PATH;
+        (*u).field_data[UF_PATH].len = buflen as u16;
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests only rely on behaviour shared by http-parser and the mock
+    //! above, so they can be run under Miri to check the unsafe glue:
+    //! `cargo +nightly miri test --lib ffi::tests`.
+
+    use std::panic::{self, AssertUnwindSafe};
+
+    use {Parser, ParserHandler};
+
+    struct Checker {
+        input: (usize, usize),
+        stop_in_url: bool,
+        pause_in_url: bool,
+        calls: usize,
+    }
+
+    impl Checker {
+        fn new(input: &[u8]) -> Checker {
+            let start = input.as_ptr() as usize;
+            Checker {
+                input: (start, start + input.len()),
+                stop_in_url: false,
+                pause_in_url: false,
+                calls: 0,
+            }
+        }
+
+        fn check(&mut self, data: &[u8]) {
+            // Reads every byte so that Miri validates the slice.
+            let _sum: u32 = data.iter().map(|&b| b as u32).sum();
+            let start = data.as_ptr() as usize;
+            assert!(start >= self.input.0 && start + data.len() <= self.input.1);
+            self.calls += 1;
+        }
+    }
+
+    impl ParserHandler for Checker {
+        fn on_url(&mut self, parser: &mut Parser, url: &[u8]) -> bool {
+            self.check(url);
+            if self.pause_in_url {
+                self.pause_in_url = false;
+                parser.pause();
+            }
+            !self.stop_in_url
+        }
+
+        fn on_body(&mut self, parser: &mut Parser, body: &[u8]) -> bool {
+            self.check(body);
+            // Touches the parser state that http-parser holds a pointer to.
+            parser.http_version();
+            true
+        }
+
+        fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+            parser.should_keep_alive();
+            true
+        }
+    }
+
+    const REQUEST: &[u8] = b"POST /ffi HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+
+    #[test]
+    fn test_new_parser_is_initialized() {
+        let parser = Parser::request();
+        assert!(parser.state.data.is_null());
+        assert!(!parser.has_error());
+        assert_eq!(parser.http_version(), (0, 0));
+    }
+
+    #[test]
+    fn test_context_cleared_after_parse() {
+        let mut parser = Parser::request();
+        let mut handler = Checker::new(REQUEST);
+
+        assert_eq!(parser.parse(&mut handler, REQUEST), REQUEST.len());
+        assert!(handler.calls > 0);
+        assert!(parser.state.data.is_null());
+
+        // The parser can be moved once `parse` has returned.
+        let moved = Box::new(parser);
+        assert!(!moved.has_error());
+    }
+
+    #[test]
+    fn test_callback_slices_within_input() {
+        let mut parser = Parser::request();
+        let mut handler = Checker::new(REQUEST);
+
+        for chunk in REQUEST.chunks(7) {
+            handler.input = (chunk.as_ptr() as usize, chunk.as_ptr() as usize + chunk.len());
+            assert!(parser.parse(&mut handler, chunk) <= chunk.len());
+        }
+        assert!(!parser.has_error());
+    }
+
+    #[test]
+    fn test_stop_from_callback() {
+        let mut parser = Parser::request();
+        let mut handler = Checker::new(REQUEST);
+        handler.stop_in_url = true;
+
+        assert!(parser.parse(&mut handler, REQUEST) < REQUEST.len());
+        assert!(parser.has_error());
+        assert!(parser.state.data.is_null());
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut parser = Parser::request();
+        let mut handler = Checker::new(REQUEST);
+        handler.pause_in_url = true;
+
+        let parsed = parser.parse(&mut handler, REQUEST);
+        assert!(parsed < REQUEST.len());
+        assert_eq!(parser.error(), "HPE_PAUSED");
+
+        parser.unpause();
+        assert!(!parser.has_error());
+        assert_eq!(parser.parse(&mut handler, &REQUEST[parsed..]), REQUEST.len() - parsed);
+        assert!(!parser.has_error());
+    }
+
+    #[test]
+    fn test_panic_is_resumed_after_execute() {
+        struct Panicking;
+
+        impl ParserHandler for Panicking {
+            fn on_url(&mut self, _: &mut Parser, _: &[u8]) -> bool {
+                panic!("from on_url");
+            }
+        }
+
+        let mut parser = Parser::request();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| parser.parse(&mut Panicking, REQUEST)));
+
+        assert!(result.is_err());
+        assert!(parser.has_error());
+        assert!(parser.state.data.is_null());
+    }
+
+    #[test]
+    fn test_eof_with_empty_input() {
+        let mut parser = Parser::request();
+        let mut handler = Checker::new(&[]);
+        assert_eq!(parser.parse(&mut handler, &[]), 0);
+        assert!(!parser.has_error());
+    }
 }
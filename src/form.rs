@@ -0,0 +1,363 @@
+//! Streaming `application/x-www-form-urlencoded` body decoding.
+//!
+//! `FormParser` decodes fields as they complete, buffering only the field
+//! that's split between body chunks, and `Form` plugs it into a `Parser` for
+//! requests with a matching `Content-Type`.
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use adapter::HeaderWatch;
+use headers::trim;
+use query;
+use {Parser, ParserHandler};
+
+/// Used to receive decoded form fields.
+pub trait FormHandler {
+    /// Called for each field with its decoded name and value. Return `false` to stop parsing.
+    fn on_field(&mut self, name: &[u8], value: &[u8]) -> bool;
+}
+
+/// An error in a form body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormError {
+    /// The body has more fields than the field count limit.
+    TooManyFields,
+    /// A field exceeds the field size limit before decoding.
+    FieldTooLarge,
+    /// A handler callback returned `false`.
+    Stopped,
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            FormError::TooManyFields => "too many form fields",
+            FormError::FieldTooLarge => "form field is too large",
+            FormError::Stopped => "stopped by the handler",
+        })
+    }
+}
+
+impl Error for FormError {}
+
+/// Checks if a `Content-Type` header value is `application/x-www-form-urlencoded`.
+pub fn is_form(content_type: &[u8]) -> bool {
+    let mime = content_type.split(|&b| b == b';').next().unwrap_or(&[]);
+    trim(mime).eq_ignore_ascii_case(b"application/x-www-form-urlencoded")
+}
+
+/// An incremental `application/x-www-form-urlencoded` parser.
+///
+/// Fields are decoded as with `query::parse`: `+` becomes a space, escapes are
+/// decoded, and empty fields are skipped. By default a body may have up to
+/// 1000 fields of up to 64 KiB each.
+///
+/// # Example
+/// ```
+/// use http_muncher::form::{FormHandler, FormParser};
+///
+/// struct Fields(Vec<(String, String)>);
+///
+/// impl FormHandler for Fields {
+///     fn on_field(&mut self, name: &[u8], value: &[u8]) -> bool {
+///         self.0.push((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned()));
+///         true
+///     }
+/// }
+///
+/// let mut fields = Fields(Vec::new());
+/// let mut parser = FormParser::new();
+///
+/// parser.parse(&mut fields, b"name=J%C3%B6rg&comment=hello+wo").unwrap();
+/// parser.parse(&mut fields, b"rld").unwrap();
+/// parser.finish(&mut fields).unwrap();
+///
+/// assert_eq!(fields.0, vec![("name".to_owned(), "Jörg".to_owned()),
+///                           ("comment".to_owned(), "hello world".to_owned())]);
+/// ```
+#[derive(Debug)]
+pub struct FormParser {
+    /// The raw start of a field split between chunks.
+    pending: Vec<u8>,
+    fields: usize,
+    max_fields: usize,
+    max_field_size: usize,
+}
+
+impl Default for FormParser {
+    fn default() -> FormParser {
+        FormParser::new()
+    }
+}
+
+impl FormParser {
+    /// Creates a parser for one body with the default limits.
+    pub fn new() -> FormParser {
+        FormParser {
+            pending: Vec::new(),
+            fields: 0,
+            max_fields: 1000,
+            max_field_size: 64 * 1024,
+        }
+    }
+
+    /// Sets the maximum number of fields.
+    pub fn max_fields(mut self, max_fields: usize) -> FormParser {
+        self.max_fields = max_fields;
+        self
+    }
+
+    /// Sets the maximum size of an encoded `name=value` field in bytes.
+    pub fn max_field_size(mut self, max_field_size: usize) -> FormParser {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    /// Parses the next chunk of the body, passing on the fields completed in it.
+    pub fn parse<H: FormHandler>(&mut self, handler: &mut H, mut data: &[u8]) -> Result<(), FormError> {
+        while let Some(amp) = data.iter().position(|&b| b == b'&') {
+            if self.pending.is_empty() {
+                self.field(handler, &data[..amp])?;
+            } else {
+                self.buffer(&data[..amp])?;
+                let field = mem::take(&mut self.pending);
+                self.field(handler, &field)?;
+            }
+            data = &data[amp + 1..];
+        }
+
+        self.buffer(data)
+    }
+
+    /// Passes on the last field once the body has ended.
+    pub fn finish<H: FormHandler>(&mut self, handler: &mut H) -> Result<(), FormError> {
+        let field = mem::take(&mut self.pending);
+        self.field(handler, &field)
+    }
+
+    fn buffer(&mut self, data: &[u8]) -> Result<(), FormError> {
+        if self.pending.len() + data.len() > self.max_field_size {
+            return Err(FormError::FieldTooLarge);
+        }
+
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn field<H: FormHandler>(&mut self, handler: &mut H, field: &[u8]) -> Result<(), FormError> {
+        if field.len() > self.max_field_size {
+            return Err(FormError::FieldTooLarge);
+        }
+
+        let (name, value) = match query::parse(field).next() {
+            Some(pair) => pair,
+            None => return Ok(()),
+        };
+
+        self.fields += 1;
+        if self.fields > self.max_fields {
+            return Err(FormError::TooManyFields);
+        }
+
+        if handler.on_field(&name, &value) {
+            Ok(())
+        } else {
+            Err(FormError::Stopped)
+        }
+    }
+}
+
+/// A handler adapter decoding `application/x-www-form-urlencoded` request bodies.
+///
+/// Bodies with a matching `Content-Type` are passed to the inner handler as
+/// `FormHandler::on_field` calls instead of `on_body` calls. Other bodies, and
+/// all other callbacks, are passed through.
+///
+/// On errors in the body the parser is stopped, and the error is available from `error`.
+pub struct Form<H> {
+    inner: H,
+    headers: HeaderWatch,
+    parser: Option<FormParser>,
+    max_fields: usize,
+    max_field_size: usize,
+    error: Option<FormError>,
+}
+
+impl<H: ParserHandler + FormHandler> Form<H> {
+    /// Wraps `inner`, with the same default limits as `FormParser`.
+    pub fn new(inner: H) -> Form<H> {
+        let defaults = FormParser::new();
+
+        Form {
+            inner,
+            headers: HeaderWatch::new("Content-Type"),
+            parser: None,
+            max_fields: defaults.max_fields,
+            max_field_size: defaults.max_field_size,
+            error: None,
+        }
+    }
+
+    /// Sets the maximum number of fields.
+    pub fn max_fields(mut self, max_fields: usize) -> Form<H> {
+        self.max_fields = max_fields;
+        self
+    }
+
+    /// Sets the maximum size of an encoded `name=value` field in bytes.
+    pub fn max_field_size(mut self, max_field_size: usize) -> Form<H> {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    inner_accessors!(H);
+
+    /// Returns the error that stopped the parser, if the body was invalid.
+    pub fn error(&self) -> Option<&FormError> {
+        self.error.as_ref()
+    }
+
+    fn check(&mut self, result: Result<(), FormError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+}
+
+impl<H: ParserHandler + FormHandler> ParserHandler for Form<H> {
+    fn on_message_begin(&mut self, parser: &mut Parser) -> bool {
+        self.headers.clear();
+        self.parser = None;
+        self.error = None;
+        self.inner.on_message_begin(parser)
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.finish();
+
+        if self.headers.last().is_some_and(is_form) {
+            self.parser = Some(FormParser::new()
+                .max_fields(self.max_fields)
+                .max_field_size(self.max_field_size));
+        }
+
+        self.inner.on_headers_complete(parser)
+    }
+
+    fn on_body(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        let result = match self.parser {
+            Some(ref mut form) => form.parse(&mut self.inner, data),
+            None => return self.inner.on_body(parser, data),
+        };
+
+        self.check(result)
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        let result = match self.parser.take() {
+            Some(mut form) => form.finish(&mut self.inner),
+            None => Ok(()),
+        };
+
+        self.check(result) && self.inner.on_message_complete(parser)
+    }
+
+    pass_through!(on_url, on_status, on_header_field, on_header_value, on_chunk_header, on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_form, Form, FormError, FormHandler, FormParser};
+    use {Parser, ParserHandler};
+
+    #[derive(Default)]
+    struct Fields(Vec<(Vec<u8>, Vec<u8>)>);
+
+    impl FormHandler for Fields {
+        fn on_field(&mut self, name: &[u8], value: &[u8]) -> bool {
+            self.0.push((name.to_vec(), value.to_vec()));
+            true
+        }
+    }
+
+    impl ParserHandler for Fields {}
+
+    const BODY: &[u8] = b"a=1&b=two+words&&a=%26&flag&last=%E2%9C%93";
+
+    fn expected() -> Vec<(Vec<u8>, Vec<u8>)> {
+        vec![(b"a".to_vec(), b"1".to_vec()),
+             (b"b".to_vec(), b"two words".to_vec()),
+             (b"a".to_vec(), b"&".to_vec()),
+             (b"flag".to_vec(), b"".to_vec()),
+             (b"last".to_vec(), "\u{2713}".as_bytes().to_vec())]
+    }
+
+    #[test]
+    fn test_is_form() {
+        assert!(is_form(b"Application/X-WWW-Form-Urlencoded; charset=UTF-8"));
+        assert!(!is_form(b"multipart/form-data; boundary=x"));
+    }
+
+    #[test]
+    fn test_fields_split_at_every_byte() {
+        for split in 0..BODY.len() {
+            let mut fields = Fields::default();
+            let mut parser = FormParser::new();
+
+            parser.parse(&mut fields, &BODY[..split]).unwrap();
+            parser.parse(&mut fields, &BODY[split..]).unwrap();
+            parser.finish(&mut fields).unwrap();
+
+            assert_eq!(fields.0, expected(), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut parser = FormParser::new().max_fields(4);
+        let mut fields = Fields::default();
+        parser.parse(&mut fields, BODY).unwrap();
+        assert_eq!(parser.finish(&mut fields), Err(FormError::TooManyFields));
+
+        let mut parser = FormParser::new().max_field_size(12);
+        assert_eq!(parser.parse(&mut Fields::default(), BODY), Err(FormError::FieldTooLarge));
+
+        // A field split between chunks is limited while it's being buffered.
+        let mut parser = FormParser::new().max_field_size(4);
+        let mut fields = Fields::default();
+        parser.parse(&mut fields, b"a=1&abc").unwrap();
+        assert_eq!(parser.parse(&mut fields, b"def"), Err(FormError::FieldTooLarge));
+    }
+
+    #[test]
+    fn test_adapter() {
+        let mut req = format!("POST /submit HTTP/1.1\r\n\
+                               Content-Type: application/x-www-form-urlencoded\r\n\
+                               Content-Length: {}\r\n\r\n",
+                              BODY.len())
+            .into_bytes();
+        req.extend_from_slice(BODY);
+
+        let mut handler = Form::new(Fields::default());
+        let mut parser = Parser::request();
+        for chunk in req.chunks(5) {
+            parser.parse(&mut handler, chunk);
+        }
+
+        assert!(!parser.has_error());
+        assert_eq!(handler.inner().0, expected());
+
+        let mut handler = Form::new(Fields::default()).max_fields(1);
+        let mut parser = Parser::request();
+        parser.parse(&mut handler, &req);
+
+        assert_eq!(parser.error(), "HPE_CB_body");
+        assert_eq!(handler.error(), Some(&FormError::TooManyFields));
+    }
+}
@@ -0,0 +1,185 @@
+//! A `ParserHandler` assembled from closures.
+
+use {Parser, ParserHandler};
+
+type DataCallback<'a> = Box<dyn FnMut(&mut Parser, &[u8]) -> bool + 'a>;
+type NotifyCallback<'a> = Box<dyn FnMut(&mut Parser) -> bool + 'a>;
+
+/// A handler built from closures with `Handler::builder()`, for cases where
+/// implementing `ParserHandler` on a named type would be overkill.
+///
+/// Callbacks that haven't been set just let the parser continue.
+///
+/// # Example
+/// ```
+/// use http_muncher::{Handler, Parser};
+///
+/// let mut url = Vec::new();
+/// let mut body = Vec::new();
+///
+/// {
+///     let mut handler = Handler::builder()
+///         .on_url(|_, data| { url.extend_from_slice(data); true })
+///         .on_body(|_, data| { body.extend_from_slice(data); true })
+///         .build();
+///
+///     let mut parser = Parser::request();
+///     parser.parse(&mut handler, b"POST /hello HTTP/1.1\r\nContent-Length: 2\r\n\r\nHi");
+/// }
+///
+/// assert_eq!(url, b"/hello");
+/// assert_eq!(body, b"Hi");
+/// ```
+#[derive(Default)]
+pub struct Handler<'a> {
+    on_url: Option<DataCallback<'a>>,
+    on_status: Option<DataCallback<'a>>,
+    on_header_field: Option<DataCallback<'a>>,
+    on_header_value: Option<DataCallback<'a>>,
+    on_body: Option<DataCallback<'a>>,
+    on_headers_complete: Option<NotifyCallback<'a>>,
+    on_message_begin: Option<NotifyCallback<'a>>,
+    on_message_complete: Option<NotifyCallback<'a>>,
+    on_chunk_header: Option<NotifyCallback<'a>>,
+    on_chunk_complete: Option<NotifyCallback<'a>>,
+}
+
+impl<'a> Handler<'a> {
+    /// Starts building a handler with every callback unset.
+    pub fn builder() -> HandlerBuilder<'a> {
+        HandlerBuilder { handler: Handler::default() }
+    }
+}
+
+/// Builds a `Handler` one callback at a time.
+pub struct HandlerBuilder<'a> {
+    handler: Handler<'a>,
+}
+
+macro_rules! data_setter {
+    ( $callback:ident, $doc:expr ) => {
+        #[doc = $doc]
+        pub fn $callback<F>(mut self, callback: F) -> HandlerBuilder<'a>
+            where F: FnMut(&mut Parser, &[u8]) -> bool + 'a
+        {
+            self.handler.$callback = Some(Box::new(callback));
+            self
+        }
+    };
+}
+
+macro_rules! notify_setter {
+    ( $callback:ident, $doc:expr ) => {
+        #[doc = $doc]
+        pub fn $callback<F>(mut self, callback: F) -> HandlerBuilder<'a>
+            where F: FnMut(&mut Parser) -> bool + 'a
+        {
+            self.handler.$callback = Some(Box::new(callback));
+            self
+        }
+    };
+}
+
+impl<'a> HandlerBuilder<'a> {
+    data_setter!(on_url, "Sets the callback for `ParserHandler::on_url`.");
+    data_setter!(on_status, "Sets the callback for `ParserHandler::on_status`.");
+    data_setter!(on_header_field, "Sets the callback for `ParserHandler::on_header_field`.");
+    data_setter!(on_header_value, "Sets the callback for `ParserHandler::on_header_value`.");
+    data_setter!(on_body, "Sets the callback for `ParserHandler::on_body`.");
+    notify_setter!(on_headers_complete, "Sets the callback for `ParserHandler::on_headers_complete`.");
+    notify_setter!(on_message_begin, "Sets the callback for `ParserHandler::on_message_begin`.");
+    notify_setter!(on_message_complete, "Sets the callback for `ParserHandler::on_message_complete`.");
+    notify_setter!(on_chunk_header, "Sets the callback for `ParserHandler::on_chunk_header`.");
+    notify_setter!(on_chunk_complete, "Sets the callback for `ParserHandler::on_chunk_complete`.");
+
+    /// Returns the handler.
+    pub fn build(self) -> Handler<'a> {
+        self.handler
+    }
+}
+
+macro_rules! data_callback {
+    ( $callback:ident ) => {
+        fn $callback(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+            match self.$callback {
+                Some(ref mut callback) => callback(parser, data),
+                None => true,
+            }
+        }
+    };
+}
+
+macro_rules! notify_callback {
+    ( $callback:ident ) => {
+        fn $callback(&mut self, parser: &mut Parser) -> bool {
+            match self.$callback {
+                Some(ref mut callback) => callback(parser),
+                None => true,
+            }
+        }
+    };
+}
+
+impl<'a> ParserHandler for Handler<'a> {
+    data_callback!(on_url);
+    data_callback!(on_status);
+    data_callback!(on_header_field);
+    data_callback!(on_header_value);
+    data_callback!(on_body);
+    notify_callback!(on_headers_complete);
+    notify_callback!(on_message_begin);
+    notify_callback!(on_message_complete);
+    notify_callback!(on_chunk_header);
+    notify_callback!(on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Handler;
+    use Parser;
+
+    #[test]
+    fn test_unset_callbacks_continue() {
+        let mut handler = Handler::builder().build();
+        let mut parser = Parser::request();
+
+        let req = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nHi";
+        assert_eq!(parser.parse(&mut handler, req), req.len());
+        assert!(!parser.has_error());
+    }
+
+    #[test]
+    fn test_closures_are_called() {
+        let mut headers = Vec::new();
+        let mut completed = 0;
+
+        {
+            let mut handler = Handler::builder()
+                .on_header_field(|_, field| {
+                    headers.push(field.to_vec());
+                    true
+                })
+                .on_message_complete(|parser| {
+                    assert!(parser.should_keep_alive());
+                    completed += 1;
+                    true
+                })
+                .build();
+
+            let mut parser = Parser::request();
+            parser.parse(&mut handler, b"GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n");
+        }
+
+        assert_eq!(headers, vec![b"Host".to_vec(), b"Accept".to_vec()]);
+        assert_eq!(completed, 1);
+    }
+
+    #[test]
+    fn test_closure_stops_parser() {
+        let mut handler = Handler::builder().on_url(|_, _| false).build();
+        let mut parser = Parser::request();
+
+        parser.parse(&mut handler, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(parser.error(), "HPE_CB_url");
+    }
+}
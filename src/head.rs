@@ -0,0 +1,282 @@
+//! Zero-copy message heads over `bytes::Bytes` buffers.
+
+use std::mem;
+
+use bytes::{Bytes, BytesMut};
+
+use {Parser, ParserHandler};
+
+/// An HTTP request or response head.
+///
+/// The URL, reason phrase and headers are slices of the buffer passed to
+/// `HeadReader::parse` when they arrived within a single buffer, and owned
+/// copies when they were split between two `parse` calls.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Head {
+    /// Request method, `None` for responses.
+    pub method: Option<&'static str>,
+    /// Request URL, empty for responses.
+    pub url: Bytes,
+    /// Response status code, `None` for requests.
+    pub status: Option<u16>,
+    /// Response reason phrase, empty for requests.
+    pub reason: Bytes,
+    /// HTTP version as `(major, minor)`.
+    pub version: (u16, u16),
+    /// Header names and values in the order they were received.
+    pub headers: Vec<(Bytes, Bytes)>,
+}
+
+impl Head {
+    /// Returns the value of the first header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&Bytes> {
+        self.headers.iter()
+            .find(|&(field, _)| field.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+}
+
+/// A piece of the head that may arrive in several callbacks.
+#[derive(Debug)]
+enum Fragment {
+    Empty,
+    /// A `(start, end)` span of the buffer currently being parsed.
+    Span(usize, usize),
+    Owned(BytesMut),
+}
+
+impl Fragment {
+    fn push(&mut self, input: &Bytes, offset: usize, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let end = offset + data.len();
+
+        *self = match mem::replace(self, Fragment::Empty) {
+            Fragment::Empty => Fragment::Span(offset, end),
+            Fragment::Span(start, prev_end) if prev_end == offset => Fragment::Span(start, end),
+            Fragment::Span(start, prev_end) => {
+                let mut owned = BytesMut::from(&input[start..prev_end]);
+                owned.extend_from_slice(data);
+                Fragment::Owned(owned)
+            }
+            Fragment::Owned(mut owned) => {
+                owned.extend_from_slice(data);
+                Fragment::Owned(owned)
+            }
+        };
+    }
+
+    /// Copies a span out of `input` before the buffer is replaced with the next one.
+    fn detach(&mut self, input: &Bytes) {
+        if let Fragment::Span(start, end) = *self {
+            *self = Fragment::Owned(BytesMut::from(&input[start..end]));
+        }
+    }
+
+    fn take(&mut self, input: &Bytes) -> Bytes {
+        match mem::replace(self, Fragment::Empty) {
+            Fragment::Empty => Bytes::new(),
+            Fragment::Span(start, end) => input.slice(start..end),
+            Fragment::Owned(owned) => owned.freeze(),
+        }
+    }
+}
+
+/// Reads message heads from `Bytes` buffers without copying them.
+///
+/// Once a head is complete the parser is paused, so that the body can be read
+/// with another handler: take the head with `take`, unpause the parser and
+/// continue from the number of bytes returned by `parse`.
+///
+/// # Example
+/// ```
+/// extern crate bytes;
+/// extern crate http_muncher;
+///
+/// use bytes::Bytes;
+/// use http_muncher::{HeadReader, Parser};
+///
+/// # fn main() {
+/// let buf = Bytes::from_static(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n");
+///
+/// let mut parser = Parser::request();
+/// let mut reader = HeadReader::new();
+/// reader.parse(&mut parser, &buf);
+///
+/// let head = reader.take().unwrap();
+/// assert_eq!(head.method, Some("GET"));
+/// assert_eq!(head.url, "/index.html");
+/// assert_eq!(head.header("host").unwrap(), "example.com");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct HeadReader {
+    input: Bytes,
+    head: Head,
+    url: Fragment,
+    reason: Fragment,
+    field: Fragment,
+    value: Fragment,
+    in_value: bool,
+    complete: bool,
+}
+
+impl Default for HeadReader {
+    fn default() -> HeadReader {
+        HeadReader::new()
+    }
+}
+
+impl HeadReader {
+    /// Creates a reader that hasn't read any head yet.
+    pub fn new() -> HeadReader {
+        HeadReader {
+            input: Bytes::new(),
+            head: Head::default(),
+            url: Fragment::Empty,
+            reason: Fragment::Empty,
+            field: Fragment::Empty,
+            value: Fragment::Empty,
+            in_value: false,
+            complete: false,
+        }
+    }
+
+    /// Parses `data` with `parser` and returns a number of bytes read.
+    pub fn parse(&mut self, parser: &mut Parser, data: &Bytes) -> usize {
+        self.input = data.clone();
+        let size = parser.parse(self, data);
+
+        let input = mem::take(&mut self.input);
+        self.url.detach(&input);
+        self.reason.detach(&input);
+        self.field.detach(&input);
+        self.value.detach(&input);
+
+        size
+    }
+
+    /// Checks if a complete head has been read and not taken yet.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Takes the head once it's complete.
+    pub fn take(&mut self) -> Option<Head> {
+        if !self.complete {
+            return None;
+        }
+
+        self.complete = false;
+        Some(mem::take(&mut self.head))
+    }
+
+    fn finish_header(&mut self) {
+        let field = self.field.take(&self.input);
+        let value = self.value.take(&self.input);
+        self.head.headers.push((field, value));
+        self.in_value = false;
+    }
+}
+
+impl ParserHandler for HeadReader {
+    fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+        self.head = Head::default();
+        self.complete = false;
+        true
+    }
+
+    fn on_url(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.url.push(&self.input, parser.current_offset(), data);
+        true
+    }
+
+    fn on_status(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.reason.push(&self.input, parser.current_offset(), data);
+        true
+    }
+
+    fn on_header_field(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        if self.in_value {
+            self.finish_header();
+        }
+        self.field.push(&self.input, parser.current_offset(), data);
+        true
+    }
+
+    fn on_header_value(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.in_value = true;
+        self.value.push(&self.input, parser.current_offset(), data);
+        true
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        if self.in_value {
+            self.finish_header();
+        }
+
+        match parser.status_code() {
+            0 => self.head.method = Some(parser.http_method()),
+            status => self.head.status = Some(status),
+        }
+        self.head.version = parser.http_version();
+        self.head.url = self.url.take(&self.input);
+        self.head.reason = self.reason.take(&self.input);
+        self.complete = true;
+
+        parser.pause();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::HeadReader;
+    use Parser;
+
+    #[test]
+    fn test_request_head_is_sliced() {
+        let buf = Bytes::from_static(b"GET /path HTTP/1.1\r\nHost: example.com\r\nX-Empty:\r\n\r\n");
+
+        let mut parser = Parser::request();
+        let mut reader = HeadReader::new();
+        reader.parse(&mut parser, &buf);
+
+        assert!(parser.is_paused());
+
+        let head = reader.take().unwrap();
+        assert_eq!(head.method, Some("GET"));
+        assert_eq!(head.status, None);
+        assert_eq!(head.version, (1, 1));
+        assert_eq!(head.url, "/path");
+        assert_eq!(head.headers, vec![(Bytes::from("Host"), Bytes::from("example.com")),
+                                      (Bytes::from("X-Empty"), Bytes::new())]);
+
+        // The URL points into the original buffer rather than a copy.
+        assert_eq!(head.url.as_ptr(), buf[4..].as_ptr());
+        assert!(reader.take().is_none());
+    }
+
+    #[test]
+    fn test_response_head_split_between_buffers() {
+        let data = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+
+        for split in 1..data.len() {
+            let mut parser = Parser::response();
+            let mut reader = HeadReader::new();
+
+            reader.parse(&mut parser, &Bytes::copy_from_slice(&data[..split]));
+            reader.parse(&mut parser, &Bytes::copy_from_slice(&data[split..]));
+
+            let head = reader.take().unwrap();
+            assert_eq!(head.status, Some(404));
+            assert_eq!(head.method, None);
+            assert_eq!(head.reason, "Not Found");
+            assert_eq!(head.header("content-length").unwrap(), "0");
+        }
+    }
+}
@@ -0,0 +1,691 @@
+//! Collecting headers from parser callbacks and parsing common header values.
+//!
+//! `Headers` reassembles headers from `on_header_field` and `on_header_value`
+//! fragments and gives typed access to the framing headers once
+//! `on_headers_complete` has been called. The parsers behind it are also
+//! available as functions over raw header values, following RFC 9110.
+//!
+//! # Example
+//! ```
+//! use http_muncher::{Parser, ParserHandler};
+//! use http_muncher::headers::Headers;
+//!
+//! struct Handler(Headers);
+//!
+//! impl ParserHandler for Handler {
+//!     fn on_header_field(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+//!         self.0.on_header_field(data);
+//!         true
+//!     }
+//!
+//!     fn on_header_value(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+//!         self.0.on_header_value(data);
+//!         true
+//!     }
+//!
+//!     fn on_headers_complete(&mut self, _: &mut Parser) -> bool {
+//!         self.0.on_headers_complete();
+//!         true
+//!     }
+//! }
+//!
+//! let mut handler = Handler(Headers::new());
+//! let mut parser = Parser::request();
+//! parser.parse(&mut handler, b"POST / HTTP/1.1\r\n\
+//!                              Host: example.com:8080\r\n\
+//!                              Content-Type: text/plain; charset=\"utf-8\"\r\n\
+//!                              Content-Length: 0\r\n\r\n");
+//!
+//! let headers = &handler.0;
+//! assert_eq!(headers.content_length(), Some(Ok(0)));
+//! assert_eq!(headers.host().unwrap().unwrap().port, Some(8080));
+//! assert_eq!(headers.content_type().unwrap().unwrap().param("charset"), Some(&b"utf-8"[..]));
+//! ```
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+/// Collects header fragments into complete `(name, value)` pairs.
+///
+/// A header is only known to be complete when the next header's name starts
+/// or the head ends, so completed pairs are returned from `on_field` and `finish`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HeaderAccumulator {
+    field: Vec<u8>,
+    value: Vec<u8>,
+    in_value: bool,
+}
+
+impl HeaderAccumulator {
+    pub fn new() -> HeaderAccumulator {
+        HeaderAccumulator::default()
+    }
+
+    /// Adds a fragment of a header name, returning the previous header if this starts a new one.
+    pub fn on_field(&mut self, data: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let complete = if self.in_value { self.finish() } else { None };
+        self.field.extend_from_slice(data);
+        complete
+    }
+
+    /// Adds a fragment of a header value.
+    pub fn on_value(&mut self, data: &[u8]) {
+        self.in_value = true;
+        self.value.extend_from_slice(data);
+    }
+
+    /// Returns the last header once the head is complete.
+    pub fn finish(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        if !self.in_value {
+            return None;
+        }
+
+        self.in_value = false;
+        Some((mem::take(&mut self.field), mem::take(&mut self.value)))
+    }
+}
+
+/// Trims leading and trailing whitespace.
+pub(crate) fn trim(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(value.len());
+    let end = value.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |pos| pos + 1);
+    &value[start..end]
+}
+
+/// Headers of a message, in the order they were received.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    pending: HeaderAccumulator,
+}
+
+impl Headers {
+    /// Creates an empty header list.
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Adds a header name fragment; call it from `ParserHandler::on_header_field`.
+    pub fn on_header_field(&mut self, data: &[u8]) {
+        if let Some(header) = self.pending.on_field(data) {
+            self.entries.push(header);
+        }
+    }
+
+    /// Adds a header value fragment; call it from `ParserHandler::on_header_value`.
+    pub fn on_header_value(&mut self, data: &[u8]) {
+        self.pending.on_value(data);
+    }
+
+    /// Adds the last header; call it from `ParserHandler::on_headers_complete`.
+    pub fn on_headers_complete(&mut self) {
+        if let Some(header) = self.pending.finish() {
+            self.entries.push(header);
+        }
+    }
+
+    /// Removes all headers, e.g. before the next message.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending = HeaderAccumulator::new();
+    }
+
+    /// Returns the number of headers, counting repeated names separately.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns all headers as `(name, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().map(|(name, value)| (&name[..], &value[..]))
+    }
+
+    /// Returns the value of the first header named `name`, compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries.iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Returns the values of all headers named `name`, compared case-insensitively.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.entries.iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Parses `Content-Length`. Repeated headers must have the same value.
+    pub fn content_length(&self) -> Option<Result<u64, HeaderError>> {
+        let mut length = None;
+
+        for value in self.get_all("content-length") {
+            let parsed = match content_length(value) {
+                Ok(parsed) => parsed,
+                Err(err) => return Some(Err(err)),
+            };
+
+            if length.is_some_and(|length| length != parsed) {
+                return Some(Err(HeaderError::new("Content-Length")));
+            }
+            length = Some(parsed);
+        }
+
+        length.map(Ok)
+    }
+
+    /// Parses `Content-Type`.
+    pub fn content_type(&self) -> Option<Result<MediaType<'_>, HeaderError>> {
+        self.get("content-type").map(content_type)
+    }
+
+    /// Parses the transfer codings listed in all `Transfer-Encoding` headers.
+    pub fn transfer_encoding(&self) -> Option<Result<Vec<&[u8]>, HeaderError>> {
+        self.list("transfer-encoding", transfer_encoding)
+    }
+
+    /// Parses the options listed in all `Connection` headers.
+    pub fn connection(&self) -> Option<Result<Vec<&[u8]>, HeaderError>> {
+        self.list("connection", connection)
+    }
+
+    /// Parses `Host`.
+    pub fn host(&self) -> Option<Result<Host<'_>, HeaderError>> {
+        self.get("host").map(host)
+    }
+
+    /// Parses `Expect`.
+    pub fn expect(&self) -> Option<Result<Expectation<'_>, HeaderError>> {
+        self.get("expect").map(expect)
+    }
+
+    /// Parses `Range`.
+    pub fn range(&self) -> Option<Result<Vec<ByteRange>, HeaderError>> {
+        self.get("range").map(range)
+    }
+
+    fn list<'a, F>(&'a self, name: &'a str, parse: F) -> Option<Result<Vec<&'a [u8]>, HeaderError>>
+        where F: Fn(&'a [u8]) -> Result<Vec<&'a [u8]>, HeaderError>
+    {
+        let mut items: Option<Vec<&[u8]>> = None;
+
+        for value in self.get_all(name) {
+            match parse(value) {
+                Ok(parsed) => items.get_or_insert_with(Vec::new).extend(parsed),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        items.map(Ok)
+    }
+}
+
+/// An invalid header value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderError {
+    name: &'static str,
+}
+
+impl HeaderError {
+    fn new(name: &'static str) -> HeaderError {
+        HeaderError { name }
+    }
+
+    /// Returns the name of the header, e.g. `Content-Length`.
+    pub fn header_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {} header", self.name)
+    }
+}
+
+impl Error for HeaderError {}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub(crate) fn is_token(value: &[u8]) -> bool {
+    !value.is_empty() && value.iter().all(|&b| is_tchar(b))
+}
+
+fn is_digits(value: &[u8]) -> bool {
+    !value.is_empty() && value.iter().all(|b| b.is_ascii_digit())
+}
+
+fn parse_u64(value: &[u8]) -> Option<u64> {
+    if !is_digits(value) {
+        return None;
+    }
+
+    value.iter().try_fold(0u64, |n, &b| n.checked_mul(10)?.checked_add((b - b'0') as u64))
+}
+
+/// Splits a comma-separated list, skipping empty elements.
+fn split_list(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value.split(|&b| b == b',').map(trim).filter(|item| !item.is_empty())
+}
+
+/// Parses a `Content-Length` value.
+///
+/// A list of identical lengths, as produced by some proxies when combining
+/// repeated headers, is accepted as that length.
+pub fn content_length(value: &[u8]) -> Result<u64, HeaderError> {
+    let mut length = None;
+
+    for item in value.split(|&b| b == b',').map(trim) {
+        let parsed = parse_u64(item).ok_or_else(|| HeaderError::new("Content-Length"))?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(HeaderError::new("Content-Length"));
+        }
+        length = Some(parsed);
+    }
+
+    length.ok_or_else(|| HeaderError::new("Content-Length"))
+}
+
+/// A media type such as `text/html; charset=utf-8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType<'a> {
+    /// The top-level type, such as `text`.
+    pub type_: &'a [u8],
+    /// The subtype, such as `html`.
+    pub subtype: &'a [u8],
+    /// Parameters with quoted values unquoted.
+    pub params: Vec<(&'a [u8], Cow<'a, [u8]>)>,
+}
+
+impl<'a> MediaType<'a> {
+    /// Checks if this is `type_/subtype`, compared case-insensitively.
+    pub fn is(&self, type_: &str, subtype: &str) -> bool {
+        self.type_.eq_ignore_ascii_case(type_.as_bytes()) && self.subtype.eq_ignore_ascii_case(subtype.as_bytes())
+    }
+
+    /// Returns the value of the parameter named `name`, compared case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&[u8]> {
+        self.params.iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| &value[..])
+    }
+}
+
+/// Parses a `Content-Type` value.
+pub fn content_type(value: &[u8]) -> Result<MediaType<'_>, HeaderError> {
+    let invalid = || HeaderError::new("Content-Type");
+
+    let value = trim(value);
+    let essence_end = value.iter().position(|&b| b == b';').unwrap_or(value.len());
+    let essence = trim(&value[..essence_end]);
+
+    let slash = essence.iter().position(|&b| b == b'/').ok_or_else(invalid)?;
+    let (type_, subtype) = (&essence[..slash], &essence[slash + 1..]);
+    if !is_token(type_) || !is_token(subtype) {
+        return Err(invalid());
+    }
+
+    let mut params = Vec::new();
+    let mut rest = &value[essence_end..];
+
+    while !rest.is_empty() {
+        // `rest` starts with `;`.
+        rest = trim(&rest[1..]);
+        if rest.is_empty() || rest[0] == b';' {
+            continue;
+        }
+
+        let eq = rest.iter().position(|&b| b == b'=').ok_or_else(invalid)?;
+        let name = &rest[..eq];
+        if !is_token(name) {
+            return Err(invalid());
+        }
+        rest = &rest[eq + 1..];
+
+        let param_value = if rest.first() == Some(&b'"') {
+            let (unquoted, len) = quoted_string(rest).ok_or_else(invalid)?;
+            rest = &rest[len..];
+            unquoted
+        } else {
+            let end = rest.iter().position(|&b| b == b';').unwrap_or(rest.len());
+            let token = trim(&rest[..end]);
+            if !is_token(token) {
+                return Err(invalid());
+            }
+            rest = &rest[end..];
+            Cow::Borrowed(token)
+        };
+
+        rest = trim(rest);
+        if !rest.is_empty() && rest[0] != b';' {
+            return Err(invalid());
+        }
+
+        params.push((name, param_value));
+    }
+
+    Ok(MediaType { type_, subtype, params })
+}
+
+/// Parses a quoted string at the start of `value`, returning its unescaped
+/// contents and the number of bytes it takes up.
+fn quoted_string(value: &[u8]) -> Option<(Cow<'_, [u8]>, usize)> {
+    let mut escaped = false;
+    let mut has_escapes = false;
+
+    for (i, &b) in value.iter().enumerate().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if b == b'\\' {
+            escaped = true;
+            has_escapes = true;
+        } else if b == b'"' {
+            let contents = &value[1..i];
+            if !has_escapes {
+                return Some((Cow::Borrowed(contents), i + 1));
+            }
+
+            let mut unescaped = Vec::with_capacity(contents.len());
+            let mut bytes = contents.iter();
+            while let Some(&b) = bytes.next() {
+                unescaped.push(if b == b'\\' { *bytes.next()? } else { b });
+            }
+            return Some((Cow::Owned(unescaped), i + 1));
+        }
+    }
+
+    None
+}
+
+/// Parses a `Transfer-Encoding` value into the names of its codings, in the
+/// order they were applied. Parameters of codings are ignored.
+pub fn transfer_encoding(value: &[u8]) -> Result<Vec<&[u8]>, HeaderError> {
+    let mut codings = Vec::new();
+
+    for item in split_list(value) {
+        let end = item.iter().position(|&b| b == b';').unwrap_or(item.len());
+        let coding = trim(&item[..end]);
+        if !is_token(coding) {
+            return Err(HeaderError::new("Transfer-Encoding"));
+        }
+        codings.push(coding);
+    }
+
+    if codings.is_empty() {
+        return Err(HeaderError::new("Transfer-Encoding"));
+    }
+    Ok(codings)
+}
+
+/// Parses a `Connection` value into its connection options, e.g. `close` or `upgrade`.
+pub fn connection(value: &[u8]) -> Result<Vec<&[u8]>, HeaderError> {
+    let options: Vec<&[u8]> = split_list(value).collect();
+
+    if options.is_empty() || !options.iter().all(|option| is_token(option)) {
+        return Err(HeaderError::new("Connection"));
+    }
+    Ok(options)
+}
+
+/// The value of a `Host` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Host<'a> {
+    /// Host name or address; IPv6 addresses are given without brackets.
+    pub host: &'a [u8],
+    /// The port, if one was given.
+    pub port: Option<u16>,
+}
+
+/// Parses a `Host` value.
+pub fn host(value: &[u8]) -> Result<Host<'_>, HeaderError> {
+    let invalid = || HeaderError::new("Host");
+    let value = trim(value);
+
+    let (host, port) = if value.first() == Some(&b'[') {
+        let end = value.iter().position(|&b| b == b']').ok_or_else(invalid)?;
+        let host = &value[1..end];
+        if host.is_empty() || !host.iter().all(|&b| b.is_ascii_hexdigit() || b == b':' || b == b'.') {
+            return Err(invalid());
+        }
+
+        match &value[end + 1..] {
+            b"" => (host, None),
+            rest if rest[0] == b':' => (host, Some(&rest[1..])),
+            _ => return Err(invalid()),
+        }
+    } else {
+        let (host, port) = match value.iter().position(|&b| b == b':') {
+            Some(colon) => (&value[..colon], Some(&value[colon + 1..])),
+            None => (value, None),
+        };
+
+        let is_reg_name_char = |b: u8| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b);
+        if !host.iter().all(|&b| is_reg_name_char(b)) {
+            return Err(invalid());
+        }
+        (host, port)
+    };
+
+    let port = match port {
+        // An empty port is allowed and means the default one.
+        Some(b"") | None => None,
+        Some(port) => {
+            let port = parse_u64(port).ok_or_else(invalid)?;
+            if port > u16::MAX as u64 {
+                return Err(invalid());
+            }
+            Some(port as u16)
+        }
+    };
+
+    Ok(Host { host, port })
+}
+
+/// The value of an `Expect` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expectation<'a> {
+    /// `100-continue`: the client waits for a `100 Continue` response before sending the body.
+    Continue,
+    /// Any other expectation, which a server can't meet.
+    Other(&'a [u8]),
+}
+
+/// Parses an `Expect` value.
+pub fn expect(value: &[u8]) -> Result<Expectation<'_>, HeaderError> {
+    let value = trim(value);
+
+    if value.is_empty() {
+        Err(HeaderError::new("Expect"))
+    } else if value.eq_ignore_ascii_case(b"100-continue") {
+        Ok(Expectation::Continue)
+    } else {
+        Ok(Expectation::Other(value))
+    }
+}
+
+/// A byte range from a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `first-last`, inclusive.
+    FromTo(u64, u64),
+    /// `first-`: from an offset to the end.
+    From(u64),
+    /// `-length`: the last bytes.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Resolves the range against a representation of `len` bytes, returning
+    /// the inclusive `(first, last)` offsets, or `None` if it isn't satisfiable.
+    pub fn bounds(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.min(len - 1))),
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Last(length) if length > 0 && len > 0 => Some((len - length.min(len), len - 1)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a `Range` value. Only the `bytes` unit is supported.
+pub fn range(value: &[u8]) -> Result<Vec<ByteRange>, HeaderError> {
+    let invalid = || HeaderError::new("Range");
+    let value = trim(value);
+
+    let eq = value.iter().position(|&b| b == b'=').ok_or_else(invalid)?;
+    if !trim(&value[..eq]).eq_ignore_ascii_case(b"bytes") {
+        return Err(invalid());
+    }
+
+    let mut ranges = Vec::new();
+
+    for spec in split_list(&value[eq + 1..]) {
+        let dash = spec.iter().position(|&b| b == b'-').ok_or_else(invalid)?;
+        let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+
+        ranges.push(match (parse_u64(first), last) {
+            (None, _) if first.is_empty() => ByteRange::Last(parse_u64(last).ok_or_else(invalid)?),
+            (Some(first), b"") => ByteRange::From(first),
+            (Some(first), last) => {
+                let last = parse_u64(last).ok_or_else(invalid)?;
+                if last < first {
+                    return Err(invalid());
+                }
+                ByteRange::FromTo(first, last)
+            }
+            (None, _) => return Err(invalid()),
+        });
+    }
+
+    if ranges.is_empty() {
+        return Err(invalid());
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    #[test]
+    fn test_fragments_are_joined() {
+        let mut headers = HeaderAccumulator::new();
+
+        assert_eq!(headers.on_field(b"Con"), None);
+        assert_eq!(headers.on_field(b"tent-Type"), None);
+        headers.on_value(b"text/");
+        headers.on_value(b"plain");
+        assert_eq!(headers.on_field(b"X-Empty"),
+                   Some((b"Content-Type".to_vec(), b"text/plain".to_vec())));
+        headers.on_value(b"");
+        assert_eq!(headers.finish(), Some((b"X-Empty".to_vec(), Vec::new())));
+        assert_eq!(headers.finish(), None);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = Headers::new();
+        for &(field, value) in &[(&b"Connection"[..], &b"keep-alive"[..]),
+                                 (b"Content-Length", b"42"),
+                                 (b"connection", b" Upgrade ,, TE"),
+                                 (b"Content-Length", b"42")] {
+            headers.on_header_field(field);
+            headers.on_header_value(value);
+        }
+        headers.on_headers_complete();
+
+        assert_eq!(headers.len(), 4);
+        assert_eq!(headers.get("CONNECTION"), Some(&b"keep-alive"[..]));
+        assert_eq!(headers.connection(), Some(Ok(vec![&b"keep-alive"[..], b"Upgrade", b"TE"])));
+        assert_eq!(headers.content_length(), Some(Ok(42)));
+        assert_eq!(headers.transfer_encoding(), None);
+
+        headers.on_header_field(b"Content-Length");
+        headers.on_header_value(b"43");
+        headers.on_headers_complete();
+        assert_eq!(headers.content_length(), Some(Err(HeaderError::new("Content-Length"))));
+
+        headers.clear();
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_content_length() {
+        assert_eq!(content_length(b"0"), Ok(0));
+        assert_eq!(content_length(b" 12 , 12"), Ok(12));
+        assert!(content_length(b"12, 13").is_err());
+        assert!(content_length(b"-1").is_err());
+        assert!(content_length(b"+1").is_err());
+        assert!(content_length(b"").is_err());
+        assert!(content_length(b"99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_content_type() {
+        let media_type = content_type(b"Text/HTML ; Charset=\"UTF-8\";;q=\"a\\\"b\" ").unwrap();
+        assert!(media_type.is("text", "html"));
+        assert_eq!(media_type.param("charset"), Some(&b"UTF-8"[..]));
+        assert_eq!(media_type.params[1], (&b"q"[..], Cow::Owned(b"a\"b".to_vec())));
+
+        assert_eq!(content_type(b"application/json").unwrap().params, vec![]);
+        assert!(content_type(b"text").is_err());
+        assert!(content_type(b"text/").is_err());
+        assert!(content_type(b"text/plain; charset").is_err());
+        assert!(content_type(b"text/plain; charset=\"utf-8").is_err());
+        assert!(content_type(b"text/plain; a=b c").is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(transfer_encoding(b"gzip, chunked"), Ok(vec![&b"gzip"[..], b"chunked"]));
+        assert_eq!(transfer_encoding(b"ext;q=1"), Ok(vec![&b"ext"[..]]));
+        assert!(transfer_encoding(b" , ").is_err());
+        assert_eq!(connection(b"close"), Ok(vec![&b"close"[..]]));
+        assert!(connection(b"close, a b").is_err());
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host(b"example.com"), Ok(Host { host: b"example.com", port: None }));
+        assert_eq!(host(b"example.com:8080"), Ok(Host { host: b"example.com", port: Some(8080) }));
+        assert_eq!(host(b"[::1]:80"), Ok(Host { host: b"::1", port: Some(80) }));
+        assert_eq!(host(b"example.com:"), Ok(Host { host: b"example.com", port: None }));
+        assert_eq!(host(b""), Ok(Host { host: b"", port: None }));
+        assert!(host(b"example.com:99999").is_err());
+        assert!(host(b"a:b:c").is_err());
+        assert!(host(b"user@example.com").is_err());
+        assert!(host(b"[::1").is_err());
+    }
+
+    #[test]
+    fn test_expect() {
+        assert_eq!(expect(b"100-Continue"), Ok(Expectation::Continue));
+        assert_eq!(expect(b"something"), Ok(Expectation::Other(b"something")));
+        assert!(expect(b"").is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(range(b"bytes=0-499, 500-, -200"),
+                   Ok(vec![ByteRange::FromTo(0, 499), ByteRange::From(500), ByteRange::Last(200)]));
+        assert!(range(b"bytes=5-1").is_err());
+        assert!(range(b"items=0-1").is_err());
+        assert!(range(b"bytes=").is_err());
+        assert!(range(b"bytes=a-b").is_err());
+
+        assert_eq!(ByteRange::FromTo(0, 499).bounds(100), Some((0, 99)));
+        assert_eq!(ByteRange::From(100).bounds(100), None);
+        assert_eq!(ByteRange::Last(200).bounds(100), Some((0, 99)));
+        assert_eq!(ByteRange::Last(0).bounds(100), None);
+    }
+}
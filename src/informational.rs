@@ -0,0 +1,251 @@
+//! Client-side handling of interim `1xx` responses.
+
+use headers::Headers;
+use {Parser, ParserHandler};
+
+/// Used to receive interim responses, such as `100 Continue` or `103 Early Hints`.
+pub trait InformationalHandler {
+    /// Called with the status code and headers of each interim response. Return `false` to stop parsing.
+    fn on_informational(&mut self, status: u16, headers: &Headers) -> bool;
+}
+
+/// A handler adapter for clients that separates interim `1xx` responses from
+/// the final response.
+///
+/// http-parser reads an interim response as a complete message without a
+/// body. This adapter passes it to the inner handler's `on_informational`
+/// instead of the usual callbacks, so that the inner handler only sees the
+/// final response, which may follow in the same buffer or a later one.
+///
+/// `101 Switching Protocols` is passed on as a final response: it ends the
+/// HTTP part of the connection, and `Parser::is_upgrade` tells if the rest of
+/// the data belongs to the new protocol.
+///
+/// # Example
+/// ```
+/// use http_muncher::{Informational, InformationalHandler, Parser, ParserHandler};
+/// use http_muncher::headers::Headers;
+///
+/// #[derive(Default)]
+/// struct Client {
+///     interim: Vec<u16>,
+///     status: u16,
+/// }
+///
+/// impl InformationalHandler for Client {
+///     fn on_informational(&mut self, status: u16, _: &Headers) -> bool {
+///         self.interim.push(status);
+///         true
+///     }
+/// }
+///
+/// impl ParserHandler for Client {
+///     fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+///         self.status = parser.status_code();
+///         true
+///     }
+/// }
+///
+/// let mut handler = Informational::new(Client::default());
+/// let mut parser = Parser::response();
+/// parser.parse(&mut handler, b"HTTP/1.1 100 Continue\r\n\r\n\
+///                              HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+///                              HTTP/1.1 204 No Content\r\n\r\n");
+///
+/// assert_eq!(handler.inner().interim, vec![100, 103]);
+/// assert_eq!(handler.inner().status, 204);
+/// ```
+#[derive(Debug)]
+pub struct Informational<H> {
+    inner: H,
+    headers: Headers,
+    begin_pending: bool,
+    interim: bool,
+}
+
+impl<H: ParserHandler + InformationalHandler> Informational<H> {
+    /// Wraps `inner`.
+    pub fn new(inner: H) -> Informational<H> {
+        Informational {
+            inner,
+            headers: Headers::new(),
+            begin_pending: false,
+            interim: false,
+        }
+    }
+
+    inner_accessors!(H);
+
+    /// Decides where the message goes once its status code is known, passing
+    /// the delayed `on_message_begin` on to the inner handler for final responses.
+    fn begin(&mut self, parser: &mut Parser) -> bool {
+        if !self.begin_pending {
+            return true;
+        }
+
+        self.begin_pending = false;
+        self.interim = parser.status_code() / 100 == 1 && parser.status_code() != 101;
+        self.interim || self.inner.on_message_begin(parser)
+    }
+}
+
+impl<H: ParserHandler + InformationalHandler> ParserHandler for Informational<H> {
+    fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+        self.headers.clear();
+        self.begin_pending = true;
+        self.interim = false;
+        true
+    }
+
+    fn on_url(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.begin(parser) && self.inner.on_url(parser, data)
+    }
+
+    fn on_status(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        self.begin(parser) && (self.interim || self.inner.on_status(parser, data))
+    }
+
+    fn on_header_field(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        if !self.begin(parser) {
+            return false;
+        }
+
+        if self.interim {
+            self.headers.on_header_field(data);
+            true
+        } else {
+            self.inner.on_header_field(parser, data)
+        }
+    }
+
+    fn on_header_value(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        if self.interim {
+            self.headers.on_header_value(data);
+            true
+        } else {
+            self.inner.on_header_value(parser, data)
+        }
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        if !self.begin(parser) {
+            return false;
+        }
+
+        if self.interim {
+            self.headers.on_headers_complete();
+            true
+        } else {
+            self.inner.on_headers_complete(parser)
+        }
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        if self.interim {
+            self.interim = false;
+            self.inner.on_informational(parser.status_code(), &self.headers)
+        } else {
+            self.inner.on_message_complete(parser)
+        }
+    }
+
+    pass_through!(on_body, on_chunk_header, on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Informational, InformationalHandler};
+    use headers::Headers;
+    use {Parser, ParserHandler};
+
+    type Header = (Vec<u8>, Vec<u8>);
+
+    #[derive(Default)]
+    struct Recorder {
+        interim: Vec<(u16, Vec<Header>)>,
+        begins: usize,
+        status: u16,
+        headers: Vec<u8>,
+        body: Vec<u8>,
+        complete: usize,
+    }
+
+    impl InformationalHandler for Recorder {
+        fn on_informational(&mut self, status: u16, headers: &Headers) -> bool {
+            let headers = headers.iter().map(|(name, value)| (name.to_vec(), value.to_vec())).collect();
+            self.interim.push((status, headers));
+            true
+        }
+    }
+
+    impl ParserHandler for Recorder {
+        fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+            self.begins += 1;
+            true
+        }
+
+        fn on_header_field(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+            self.headers.extend_from_slice(data);
+            true
+        }
+
+        fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+            self.status = parser.status_code();
+            true
+        }
+
+        fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+            self.body.extend_from_slice(data);
+            true
+        }
+
+        fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+            self.complete += 1;
+            true
+        }
+    }
+
+    #[test]
+    fn test_interim_responses_split_between_buffers() {
+        let data = b"HTTP/1.1 100 Continue\r\n\r\n\
+                     HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\n\r\n\
+                     HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        for split in 1..data.len() {
+            let mut parser = Parser::response();
+            let mut handler = Informational::new(Recorder::default());
+
+            parser.parse(&mut handler, &data[..split]);
+            parser.parse(&mut handler, &data[split..]);
+            assert!(!parser.has_error(), "split at {}", split);
+
+            let recorder = handler.into_inner();
+            assert_eq!(recorder.interim, vec![(100, vec![]),
+                                              (103, vec![(b"Link".to_vec(), b"</a.css>; rel=preload".to_vec())])]);
+            assert_eq!(recorder.begins, 1);
+            assert_eq!(recorder.status, 200);
+            assert_eq!(recorder.headers, b"Content-Length");
+            assert_eq!(recorder.body, b"hello");
+            assert_eq!(recorder.complete, 1);
+        }
+    }
+
+    #[test]
+    fn test_switching_protocols_is_final() {
+        let data = b"HTTP/1.1 100 Continue\r\n\r\n\
+                     HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n\
+                     websocket frames";
+
+        let mut parser = Parser::response();
+        let mut handler = Informational::new(Recorder::default());
+        let size = parser.parse(&mut handler, data);
+
+        assert!(parser.is_upgrade());
+        assert_eq!(&data[size..], b"websocket frames");
+
+        let recorder = handler.into_inner();
+        assert_eq!(recorder.interim, vec![(100, vec![])]);
+        assert_eq!(recorder.status, 101);
+        assert_eq!(recorder.complete, 1);
+    }
+}
@@ -1,28 +1,119 @@
 extern crate libc;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "decompress")]
+extern crate brotli_decompressor;
+#[cfg(feature = "decompress")]
+extern crate flate2;
 
 mod ffi;
-
+#[macro_use]
+mod adapter;
+mod error;
+mod handler;
+mod pool;
+pub mod headers;
+mod expect;
+mod informational;
+mod pipeline;
+mod server_conn;
+mod client_conn;
+#[cfg(feature = "bytes")]
+mod head;
+#[cfg(feature = "decompress")]
+mod decompress;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "client")]
+pub mod client;
+
+pub mod query;
+pub mod url;
+pub mod multipart;
+pub mod form;
+pub mod writer;
+pub mod proxy;
+
+use std::any::Any;
 use std::marker::Send;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
 
 use ffi::*;
 
-
-struct ParserContext<'a, H: ParserHandler + 'a> {
-    parser: &'a mut Parser,
+pub use error::{ConnectionError, HttpError, ParseError};
+pub use ffi::ParserType;
+pub use handler::{Handler, HandlerBuilder};
+pub use pool::ParserPool;
+pub use expect::ExpectContinue;
+pub use informational::{Informational, InformationalHandler};
+pub use pipeline::{Message, Messages, Pipeline};
+pub use server_conn::{RequestHead, ServerConnection, ServerEvent};
+pub use client_conn::{ClientConnection, ClientEvent, ResponseHead};
+#[cfg(feature = "bytes")]
+pub use head::{Head, HeadReader};
+#[cfg(feature = "decompress")]
+pub use decompress::{Decompress, DecompressError};
+
+
+struct ParserContext<'a, H: FallibleParserHandler + 'a> {
+    parser: *mut Parser,
     handler: &'a mut H,
+    input: *const libc::c_char,
+    error: Option<H::Error>,
+    panic: Option<Box<dyn Any + Send + 'static>>,
 }
 
 #[inline]
-unsafe fn unwrap_context<'a, H: ParserHandler>(http: *mut HttpParser) -> &'a mut ParserContext<'a, H> {
+unsafe fn unwrap_context<'a, H: FallibleParserHandler>(http: *mut HttpParser) -> &'a mut ParserContext<'a, H> {
     &mut *((*http).data as *mut ParserContext<H>)
 }
 
+/// Runs a handler callback and converts its result to a return code for http-parser.
+///
+/// Unwinding into the C frames of `http_parser_execute` is undefined behaviour,
+/// so a panic is caught here and the parser is stopped; `Parser::parse` resumes
+/// the panic once http-parser has returned.
+#[inline]
+fn invoke_callback<H, F>(context: &mut ParserContext<H>, callback: F) -> libc::c_int
+    where H: FallibleParserHandler,
+          F: FnOnce(&mut H, &mut Parser) -> Result<(), H::Error>
+{
+    // Any code other than 0 or 1 is an error for every callback, including
+    // `on_headers_complete` which treats 1 as "skip the body".
+    const ABORT: libc::c_int = -1;
+
+    if context.error.is_some() || context.panic.is_some() {
+        return ABORT;
+    }
+
+    let parser = unsafe {
+        // Keep the method, status code and error number up to date for the callback.
+        (*context.parser).flags = http_get_struct_flags(ptr::addr_of!((*context.parser).state));
+        &mut *context.parser
+    };
+    let handler = &mut *context.handler;
+
+    match panic::catch_unwind(AssertUnwindSafe(|| callback(handler, parser))) {
+        Ok(Ok(())) => 0,
+        Ok(Err(error)) => {
+            context.error = Some(error);
+            ABORT
+        }
+        Err(payload) => {
+            context.panic = Some(payload);
+            ABORT
+        }
+    }
+}
+
 macro_rules! notify_fn_wrapper {
     ( $callback:ident ) => ({
-        extern "C" fn $callback<H: ParserHandler>(http: *mut HttpParser) -> libc::c_int {
+        extern "C" fn $callback<H: FallibleParserHandler>(http: *mut HttpParser) -> libc::c_int {
             let context = unsafe { unwrap_context::<H>(http) };
-            if context.handler.$callback(context.parser) { 0 } else { 1 }
-        };
+            invoke_callback(context, |handler, parser| handler.$callback(parser))
+        }
 
         $callback::<H>
     });
@@ -30,25 +121,41 @@ macro_rules! notify_fn_wrapper {
 
 macro_rules! data_fn_wrapper {
     ( $callback:ident ) => ({
-        extern "C" fn $callback<H: ParserHandler>(http: *mut HttpParser, data: *const u32, size: libc::size_t) -> libc::c_int {
-            let slice = unsafe { std::slice::from_raw_parts(data as *const u8, size as usize) };
+        extern "C" fn $callback<H: FallibleParserHandler>(http: *mut HttpParser, data: *const libc::c_char, size: libc::size_t) -> libc::c_int {
+            let slice = if size == 0 {
+                &[][..]
+            } else {
+                unsafe { std::slice::from_raw_parts(data as *const u8, size as usize) }
+            };
             let context = unsafe { unwrap_context::<H>(http) };
-            if context.handler.$callback(context.parser, slice) { 0 } else { 1 }
-        };
+            if size != 0 {
+                unsafe { (*context.parser).offset = data as usize - context.input as usize };
+            }
+            invoke_callback(context, |handler, parser| handler.$callback(parser, slice))
+        }
 
         $callback::<H>
     });
 }
 
+extern "C" fn on_headers_complete<H: FallibleParserHandler>(http: *mut HttpParser) -> libc::c_int {
+    let context = unsafe { unwrap_context::<H>(http) };
+    let code = invoke_callback(context, |handler, parser| handler.on_headers_complete(parser));
+
+    // Returning 1 tells http-parser that the message has no body.
+    let skip_body = unsafe { mem::replace(&mut (*context.parser).skip_body, false) };
+    if code == 0 && skip_body { 1 } else { code }
+}
+
 impl HttpParserSettings {
-    fn new<H: ParserHandler>() -> HttpParserSettings {
+    fn new<H: FallibleParserHandler>() -> HttpParserSettings {
         HttpParserSettings {
             on_url: data_fn_wrapper!(on_url),
             on_message_begin: notify_fn_wrapper!(on_message_begin),
             on_status: data_fn_wrapper!(on_status),
             on_header_field: data_fn_wrapper!(on_header_field),
             on_header_value: data_fn_wrapper!(on_header_value),
-            on_headers_complete: notify_fn_wrapper!(on_headers_complete),
+            on_headers_complete: on_headers_complete::<H>,
             on_body: data_fn_wrapper!(on_body),
             on_message_complete: notify_fn_wrapper!(on_message_complete),
             on_chunk_header: notify_fn_wrapper!(on_chunk_header),
@@ -124,6 +231,128 @@ pub trait ParserHandler: Sized {
     }
 }
 
+/// A variant of `ParserHandler` with callbacks that can fail with an error of your own.
+///
+/// Returning `Err` from a callback stops the parser, and the error is returned from
+/// `Parser::try_parse` as `ParseError::Callback`. This lets an application reject
+/// a message (e.g. because of a forbidden path or a header that's too large) and
+/// tell why once the parser has returned.
+///
+/// All callbacks provide a default no-op implementation (i.e. they just return `Ok(())`).
+///
+/// # Example
+/// ```
+/// use http_muncher::{FallibleParserHandler, ParseError, Parser};
+///
+/// struct Router;
+/// impl FallibleParserHandler for Router {
+///     type Error = &'static str;
+///
+///     fn on_url(&mut self, _: &mut Parser, url: &[u8]) -> Result<(), &'static str> {
+///         if url.starts_with(b"/admin") {
+///             return Err("forbidden path");
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let mut parser = Parser::request();
+/// match parser.try_parse(&mut Router, b"GET /admin HTTP/1.1\r\n\r\n") {
+///     Err(ParseError::Callback(reason)) => assert_eq!(reason, "forbidden path"),
+///     _ => unreachable!(),
+/// }
+/// ```
+#[allow(unused_variables)]
+pub trait FallibleParserHandler: Sized {
+    /// The error returned from callbacks to stop the parser.
+    type Error;
+
+    /// Called when the URL part of a request becomes available.
+    fn on_url(&mut self, parser: &mut Parser, url: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called when a response status becomes available.
+    fn on_status(&mut self, parser: &mut Parser, status: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for each HTTP header key part.
+    fn on_header_field(&mut self, parser: &mut Parser, field: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for each HTTP header value part.
+    fn on_header_value(&mut self, parser: &mut Parser, value: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called with body text as an argument when the new part becomes available.
+    fn on_body(&mut self, parser: &mut Parser, body: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Notified when all available headers have been processed.
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Notified when the parser receives first bytes to parse.
+    fn on_message_begin(&mut self, parser: &mut Parser) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Notified when the parser has finished its job.
+    fn on_message_complete(&mut self, parser: &mut Parser) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Notified when a new chunk header of a chunked body has been parsed.
+    fn on_chunk_header(&mut self, parser: &mut Parser) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Notified when a chunk of a chunked body has been parsed.
+    fn on_chunk_complete(&mut self, parser: &mut Parser) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Drives a `ParserHandler` through the fallible callbacks: `false` becomes
+/// an error without a value, which stops the parser.
+struct Plain<'a, H: 'a>(&'a mut H);
+
+macro_rules! plain_notify_fn {
+    ( $callback:ident ) => {
+        fn $callback(&mut self, parser: &mut Parser) -> Result<(), ()> {
+            if self.0.$callback(parser) { Ok(()) } else { Err(()) }
+        }
+    };
+}
+
+macro_rules! plain_data_fn {
+    ( $callback:ident ) => {
+        fn $callback(&mut self, parser: &mut Parser, data: &[u8]) -> Result<(), ()> {
+            if self.0.$callback(parser, data) { Ok(()) } else { Err(()) }
+        }
+    };
+}
+
+impl<'a, H: ParserHandler> FallibleParserHandler for Plain<'a, H> {
+    type Error = ();
+
+    plain_data_fn!(on_url);
+    plain_data_fn!(on_status);
+    plain_data_fn!(on_header_field);
+    plain_data_fn!(on_header_value);
+    plain_data_fn!(on_body);
+    plain_notify_fn!(on_headers_complete);
+    plain_notify_fn!(on_message_begin);
+    plain_notify_fn!(on_message_complete);
+    plain_notify_fn!(on_chunk_header);
+    plain_notify_fn!(on_chunk_complete);
+}
+
 fn http_method_name(method_code: u8) -> &'static str {
     unsafe {
         let method_str = http_method_str(method_code);
@@ -178,6 +407,8 @@ pub struct Parser {
     state: HttpParser,
     parser_type: ParserType,
     flags: u32,
+    offset: usize,
+    skip_body: bool,
 }
 
 unsafe impl Send for Parser {}
@@ -189,6 +420,8 @@ impl Parser {
             parser_type: ParserType::HttpResponse,
             state: HttpParser::new(ParserType::HttpResponse),
             flags: 0,
+            offset: 0,
+            skip_body: false,
         }
     }
 
@@ -198,6 +431,8 @@ impl Parser {
             parser_type: ParserType::HttpRequest,
             state: HttpParser::new(ParserType::HttpRequest),
             flags: 0,
+            offset: 0,
+            skip_body: false,
         }
     }
 
@@ -207,28 +442,113 @@ impl Parser {
             parser_type: ParserType::HttpBoth,
             state: HttpParser::new(ParserType::HttpBoth),
             flags: 0,
+            offset: 0,
+            skip_body: false,
         }
     }
 
     /// Parses the provided `data` and returns a number of bytes read.
+    ///
+    /// Empty `data` tells the parser that the input has ended, which is how
+    /// `finish` is implemented; use `finish` instead to find out if the
+    /// message was complete.
+    ///
+    /// # Panics
+    ///
+    /// If a handler callback panics, the parser is stopped and the panic is
+    /// resumed from this method after http-parser has returned. The parser is
+    /// left in an error state and shouldn't be used to parse further data.
     pub fn parse<H: ParserHandler>(&mut self, handler: &mut H, data: &[u8]) -> usize {
+        self.execute(&mut Plain(handler), data).0
+    }
+
+    /// Parses the provided `data` with a fallible handler and returns a number of bytes read.
+    ///
+    /// An error returned from a callback is passed on as `ParseError::Callback`,
+    /// and a parsing error as `ParseError::Http`. Pausing the parser from a
+    /// callback isn't an error: the number of bytes read up to that point is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics from callbacks are resumed in the same way as in `parse`.
+    pub fn try_parse<H: FallibleParserHandler>(&mut self, handler: &mut H, data: &[u8]) -> Result<usize, ParseError<H::Error>> {
+        let (size, error) = self.execute(handler, data);
+
+        if let Some(error) = error {
+            return Err(ParseError::Callback(error));
+        }
+
+        if self.has_error() && !self.is_paused() {
+            return Err(ParseError::Http(HttpError::new(self.http_errnum())));
+        }
+
+        Ok(size)
+    }
+
+    /// Tells the parser that the input has ended, e.g. because the connection
+    /// has been closed.
+    ///
+    /// This completes a response whose body is read until the connection is
+    /// closed, calling `on_message_complete`. If the input ends in the middle
+    /// of a message, it's truncated and `HPE_INVALID_EOF_STATE` is returned.
+    /// Ending the input between messages isn't an error.
+    ///
+    /// A paused parser has to be unpaused first, since it wouldn't parse
+    /// anything and couldn't tell whether a message is complete: `HPE_PAUSED`
+    /// is returned instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics from callbacks are resumed in the same way as in `parse`.
+    pub fn finish<H: ParserHandler>(&mut self, handler: &mut H) -> Result<(), ParseError> {
+        if self.is_paused() {
+            return Err(ParseError::Http(HttpError::new(self.http_errnum())));
+        }
+
+        self.execute(&mut Plain(handler), &[]);
+
+        if self.has_error() && !self.is_paused() {
+            return Err(ParseError::Http(HttpError::new(self.http_errnum())));
+        }
+
+        Ok(())
+    }
+
+    fn execute<H: FallibleParserHandler>(&mut self, handler: &mut H, data: &[u8]) -> (usize, Option<H::Error>) {
+        // Both the context and http-parser reach the parser through this one
+        // raw pointer, so the `&mut Parser` handed to callbacks doesn't
+        // invalidate the pointer to `state` that http-parser keeps using.
+        let parser: *mut Parser = self;
+
+        let mut context = ParserContext {
+            parser,
+            handler,
+            input: data.as_ptr() as *const libc::c_char,
+            error: None,
+            panic: None,
+        };
+
         unsafe {
-            let mut context = ParserContext {
-                parser: self,
-                handler: handler,
-            };
+            (*parser).offset = 0;
 
-            context.parser.state.data = &mut context as *mut _ as *mut libc::c_void;
+            let state = ptr::addr_of_mut!((*parser).state);
+            (*state).data = &mut context as *mut _ as *mut libc::c_void;
 
             let size =
-                http_parser_execute(&mut context.parser.state as *mut _,
+                http_parser_execute(state,
                                     &HttpParserSettings::new::<H>() as *const _,
-                                    data.as_ptr(),
+                                    data.as_ptr() as *const libc::c_char,
                                     data.len() as libc::size_t) as usize;
 
-            context.parser.flags = http_get_struct_flags(&context.parser.state as *const _);
+            // Don't leave a pointer to the context behind once it goes out of scope.
+            (*state).data = ptr::null_mut();
+            (*parser).flags = http_get_struct_flags(state);
 
-            size
+            if let Some(payload) = context.panic.take() {
+                panic::resume_unwind(payload);
+            }
+
+            (size, context.error)
         }
     }
 
@@ -252,6 +572,21 @@ impl Parser {
         return ((self.flags >> 24) & 0x7F) as u8;
     }
 
+    /// Returns the offset of the current data callback's slice from the start
+    /// of the buffer passed to `parse`.
+    ///
+    /// Use it to slice the original buffer instead of copying the data, e.g. with
+    /// `bytes::Bytes::slice`. It's only meaningful inside data callbacks
+    /// (`on_url`, `on_status`, `on_header_field`, `on_header_value` and `on_body`).
+    pub fn current_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Checks if the parser has been paused from a callback.
+    pub fn is_paused(&self) -> bool {
+        self.has_error() && self.error() == "HPE_PAUSED"
+    }
+
     /// Checks if the last `parse` call was finished successfully.
     /// Returns `true` if it wasn't.
     pub fn has_error(&self) -> bool {
@@ -288,10 +623,50 @@ impl Parser {
 
     pub fn pause(&mut self) {
         self.state.http_parser_pause(1);
+        self.refresh_flags();
     }
 
     pub fn unpause(&mut self) {
         self.state.http_parser_pause(0);
+        self.refresh_flags();
+    }
+
+    /// Updates the cached flags after the error number has changed outside of `execute`.
+    fn refresh_flags(&mut self) {
+        self.flags = unsafe { http_get_struct_flags(&self.state) };
+    }
+
+    /// Tells the parser that the current message has no body, whatever its
+    /// headers say. Only has an effect when called from `on_headers_complete`.
+    ///
+    /// A client has to call it for responses to `HEAD` requests, and for
+    /// successful responses to `CONNECT`, since the parser can't tell from a
+    /// response alone that its `Content-Length` doesn't describe a body.
+    pub fn skip_body(&mut self) {
+        self.skip_body = true;
+    }
+
+    /// Returns the kind of messages the parser handles.
+    pub fn parser_type(&self) -> ParserType {
+        self.parser_type
+    }
+
+    /// Resets the parser to its initial state, dropping any partially parsed message
+    /// and clearing errors, so it can be reused for a new connection.
+    pub fn reset(&mut self) {
+        let parser_type = self.parser_type;
+        self.reset_as(parser_type);
+    }
+
+    /// Resets the parser to its initial state, switching it to handle `parser_type` messages.
+    pub fn reset_as(&mut self, parser_type: ParserType) {
+        unsafe {
+            http_parser_init(&mut self.state, parser_type);
+        }
+        self.parser_type = parser_type;
+        self.flags = 0;
+        self.offset = 0;
+        self.skip_body = false;
     }
 }
 
@@ -324,7 +699,9 @@ pub fn version() -> (u32, u32, u32) {
 
 #[cfg(test)]
 mod tests {
-    use super::{version, ParserHandler, Parser};
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::{version, FallibleParserHandler, ParseError, ParserHandler, Parser, ParserType};
 
     #[test]
     fn test_version() {
@@ -484,4 +861,258 @@ mod tests {
         assert!(parser.has_error());
         assert_eq!(parser.error(), "HPE_INVALID_METHOD");
     }
+
+    #[test]
+    fn test_reset() {
+        struct DummyHandler;
+
+        impl ParserHandler for DummyHandler {}
+
+        let mut parser = Parser::request();
+        parser.parse(&mut DummyHandler, b"UNKNOWN_METHOD / HTTP/1.1\r\n\r\n");
+        assert!(parser.has_error());
+
+        parser.reset();
+        parser.parse(&mut DummyHandler, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!parser.has_error());
+        assert_eq!(parser.http_method(), "GET");
+
+        parser.reset_as(ParserType::HttpResponse);
+        parser.parse(&mut DummyHandler, b"HTTP/1.1 200 OK\r\n\r\n");
+        assert!(!parser.has_error());
+        assert_eq!(parser.status_code(), 200);
+    }
+
+    #[test]
+    fn test_panic_in_callback() {
+        struct PanickingHandler {
+            body_parsed: bool,
+        }
+
+        impl ParserHandler for PanickingHandler {
+            fn on_headers_complete(&mut self, _: &mut Parser) -> bool {
+                panic!("headers rejected");
+            }
+
+            fn on_body(&mut self, _: &mut Parser, _: &[u8]) -> bool {
+                self.body_parsed = true;
+                true
+            }
+        }
+
+        let req = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello";
+
+        let mut handler = PanickingHandler { body_parsed: false };
+        let mut parser = Parser::request();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| parser.parse(&mut handler, req)));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"headers rejected"));
+        assert!(!handler.body_parsed);
+        assert!(parser.has_error());
+    }
+
+    #[test]
+    fn test_fallible_handler() {
+        #[derive(Debug, PartialEq)]
+        enum Rejection {
+            HeaderTooLarge(usize),
+        }
+
+        struct LimitingHandler {
+            body: Vec<u8>,
+        }
+
+        impl FallibleParserHandler for LimitingHandler {
+            type Error = Rejection;
+
+            fn on_header_value(&mut self, _: &mut Parser, value: &[u8]) -> Result<(), Rejection> {
+                if value.len() > 8 {
+                    return Err(Rejection::HeaderTooLarge(value.len()));
+                }
+                Ok(())
+            }
+
+            fn on_body(&mut self, _: &mut Parser, body: &[u8]) -> Result<(), Rejection> {
+                self.body.extend_from_slice(body);
+                Ok(())
+            }
+        }
+
+        let mut handler = LimitingHandler { body: Vec::new() };
+        let mut parser = Parser::request();
+        let req = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nHi";
+        assert_eq!(parser.try_parse(&mut handler, req), Ok(req.len()));
+        assert_eq!(handler.body, b"Hi");
+
+        let mut parser = Parser::request();
+        let req = b"GET / HTTP/1.1\r\nCookie: 0123456789\r\n\r\n";
+        assert_eq!(parser.try_parse(&mut handler, req),
+                   Err(ParseError::Callback(Rejection::HeaderTooLarge(10))));
+        assert!(parser.has_error());
+
+        let mut parser = Parser::request();
+        match parser.try_parse(&mut handler, b"UNKNOWN_METHOD / HTTP/1.1\r\n\r\n") {
+            Err(ParseError::Http(err)) => assert_eq!(err.name(), "HPE_INVALID_METHOD"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stop_in_headers_complete() {
+        struct StoppingHandler {
+            body_parsed: bool,
+        }
+
+        impl ParserHandler for StoppingHandler {
+            fn on_headers_complete(&mut self, _: &mut Parser) -> bool {
+                false
+            }
+
+            fn on_body(&mut self, _: &mut Parser, _: &[u8]) -> bool {
+                self.body_parsed = true;
+                true
+            }
+        }
+
+        let req = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello";
+
+        let mut handler = StoppingHandler { body_parsed: false };
+        let mut parser = Parser::request();
+        parser.parse(&mut handler, req);
+
+        assert!(!handler.body_parsed);
+        assert_eq!(parser.error(), "HPE_CB_headers_complete");
+    }
+
+    #[test]
+    fn test_finish() {
+        struct BodyHandler {
+            body: Vec<u8>,
+            complete: bool,
+        }
+
+        impl ParserHandler for BodyHandler {
+            fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+                self.body.extend_from_slice(data);
+                true
+            }
+
+            fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+                self.complete = true;
+                true
+            }
+        }
+
+        // The body of a response without a length ends with the input.
+        let mut handler = BodyHandler { body: Vec::new(), complete: false };
+        let mut parser = Parser::response();
+        parser.parse(&mut handler, b"HTTP/1.1 200 OK\r\n\r\nuntil the end");
+        assert!(!handler.complete);
+
+        assert_eq!(parser.finish(&mut handler), Ok(()));
+        assert!(handler.complete);
+        assert_eq!(handler.body, b"until the end");
+
+        // A message with a length can't end early.
+        let mut handler = BodyHandler { body: Vec::new(), complete: false };
+        let mut parser = Parser::response();
+        parser.parse(&mut handler, b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\ntrunc");
+
+        let err = parser.finish(&mut handler).unwrap_err();
+        assert!(matches!(err, ParseError::Http(ref err) if err.name() == "HPE_INVALID_EOF_STATE"));
+        assert!(!handler.complete);
+
+        // A paused parser can't tell if the message is complete.
+        let mut handler = BodyHandler { body: Vec::new(), complete: false };
+        let mut parser = Parser::response();
+        parser.parse(&mut handler, b"HTTP/1.1 200 OK\r\n\r\nuntil the end");
+        parser.pause();
+
+        let err = parser.finish(&mut handler).unwrap_err();
+        assert!(matches!(err, ParseError::Http(ref err) if err.name() == "HPE_PAUSED"));
+        assert!(!handler.complete);
+
+        // Nothing is missing between messages.
+        let mut handler = BodyHandler { body: Vec::new(), complete: false };
+        let mut parser = Parser::request();
+        parser.parse(&mut handler, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(parser.finish(&mut handler), Ok(()));
+    }
+
+    #[test]
+    fn test_skip_body() {
+        struct HeadResponseHandler {
+            body_parsed: bool,
+            messages: usize,
+        }
+
+        impl ParserHandler for HeadResponseHandler {
+            fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+                parser.skip_body();
+                true
+            }
+
+            fn on_body(&mut self, _: &mut Parser, _: &[u8]) -> bool {
+                self.body_parsed = true;
+                true
+            }
+
+            fn on_message_complete(&mut self, _: &mut Parser) -> bool {
+                self.messages += 1;
+                true
+            }
+        }
+
+        let res = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+
+        let mut handler = HeadResponseHandler { body_parsed: false, messages: 0 };
+        let mut parser = Parser::response();
+        let parsed = parser.parse(&mut handler, res);
+
+        assert_eq!(parsed, res.len());
+        assert!(!parser.has_error());
+        assert!(!handler.body_parsed);
+        assert_eq!(handler.messages, 2);
+    }
+
+    #[test]
+    fn test_current_offset() {
+        struct OffsetHandler {
+            slices: Vec<(usize, Vec<u8>)>,
+        }
+
+        impl ParserHandler for OffsetHandler {
+            fn on_url(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+                self.slices.push((parser.current_offset(), data.to_vec()));
+                true
+            }
+
+            fn on_header_value(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+                self.slices.push((parser.current_offset(), data.to_vec()));
+                true
+            }
+
+            fn on_body(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+                self.slices.push((parser.current_offset(), data.to_vec()));
+                true
+            }
+        }
+
+        let req = b"POST /path HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello";
+
+        let mut handler = OffsetHandler { slices: Vec::new() };
+        let mut parser = Parser::request();
+
+        // Offsets are relative to each buffer passed to `parse`.
+        let (head, body) = req.split_at(req.len() - 3);
+        parser.parse(&mut handler, head);
+        parser.parse(&mut handler, body);
+
+        assert_eq!(handler.slices, vec![(5, b"/path".to_vec()),
+                                        (37, b"5".to_vec()),
+                                        (42, b"He".to_vec()),
+                                        (0, b"llo".to_vec())]);
+    }
 }
@@ -0,0 +1,635 @@
+//! Streaming `multipart/form-data` parsing for request bodies.
+//!
+//! `MultipartParser` splits a body into parts as it arrives, without buffering
+//! part contents, and `Multipart` plugs it into a `Parser` by taking the
+//! boundary from the request's `Content-Type` header.
+
+use std::error::Error;
+use std::fmt;
+
+use adapter::HeaderWatch;
+use headers::trim;
+use {Parser, ParserHandler};
+
+/// Maximum size of the headers of a single part.
+const MAX_PART_HEADERS_SIZE: usize = 8 * 1024;
+
+/// Used to receive parts of a multipart body.
+///
+/// As with `ParserHandler`, return `false` to stop parsing. All callbacks
+/// provide a default no-op implementation.
+#[allow(unused_variables)]
+pub trait MultipartHandler {
+    /// Called when a new part starts, before its headers.
+    fn on_part_begin(&mut self) -> bool {
+        true
+    }
+
+    /// Called for each header of the current part, e.g. `Content-Disposition`.
+    fn on_part_header(&mut self, name: &[u8], value: &[u8]) -> bool {
+        true
+    }
+
+    /// Called with a piece of the current part's content. The content may be
+    /// split into any number of pieces.
+    fn on_part_data(&mut self, data: &[u8]) -> bool {
+        true
+    }
+
+    /// Called when the current part ends.
+    fn on_part_end(&mut self) -> bool {
+        true
+    }
+}
+
+/// An error in a multipart body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultipartError {
+    /// A part header isn't a `name: value` line.
+    InvalidHeader,
+    /// The headers of a part exceed 8 KiB.
+    HeadersTooLarge,
+    /// The content of a part exceeds the part size limit.
+    PartTooLarge,
+    /// The body exceeds the total size limit.
+    BodyTooLarge,
+    /// The body ended before the closing boundary.
+    Incomplete,
+    /// A handler callback returned `false`.
+    Stopped,
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MultipartError::InvalidHeader => "invalid part header",
+            MultipartError::HeadersTooLarge => "part headers are too large",
+            MultipartError::PartTooLarge => "part is too large",
+            MultipartError::BodyTooLarge => "multipart body is too large",
+            MultipartError::Incomplete => "multipart body ended before the closing boundary",
+            MultipartError::Stopped => "stopped by the handler",
+        })
+    }
+}
+
+impl Error for MultipartError {}
+
+/// Returns the boundary of a `multipart/form-data` `Content-Type` header value.
+///
+/// # Example
+/// ```
+/// use http_muncher::multipart;
+///
+/// assert_eq!(multipart::boundary(b"multipart/form-data; boundary=\"abc 123\""),
+///            Some(b"abc 123".to_vec()));
+/// assert_eq!(multipart::boundary(b"text/plain"), None);
+/// ```
+pub fn boundary(content_type: &[u8]) -> Option<Vec<u8>> {
+    let mut params = content_type.split(|&b| b == b';');
+
+    let mime = trim(params.next().unwrap_or(&[]));
+    if !mime.eq_ignore_ascii_case(b"multipart/form-data") {
+        return None;
+    }
+
+    for param in params {
+        let param = trim(param);
+        let eq = match param.iter().position(|&b| b == b'=') {
+            Some(eq) => eq,
+            None => continue,
+        };
+
+        if !trim(&param[..eq]).eq_ignore_ascii_case(b"boundary") {
+            continue;
+        }
+
+        let mut value = trim(&param[eq + 1..]);
+        if value.len() >= 2 && value[0] == b'"' && value[value.len() - 1] == b'"' {
+            value = &value[1..value.len() - 1];
+        }
+
+        // RFC 2046 limits boundaries to 70 characters, none of which are CR or LF.
+        if value.is_empty() || value.len() > 70 || value.iter().any(|&b| b == b'\r' || b == b'\n') {
+            return None;
+        }
+
+        return Some(value.to_vec());
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Skipping everything before the first boundary.
+    Preamble,
+    /// After a boundary, expecting either `--` or a line break.
+    AfterBoundary,
+    /// After a boundary and `-`, expecting the second `-` of the closing boundary.
+    Closing,
+    /// After a boundary and CR, expecting LF.
+    BoundaryLf,
+    Headers,
+    Data,
+    /// After the closing boundary; everything is ignored.
+    Epilogue,
+}
+
+/// An incremental `multipart/form-data` parser.
+///
+/// # Example
+/// ```
+/// use http_muncher::multipart::{MultipartHandler, MultipartParser};
+///
+/// #[derive(Default)]
+/// struct Fields(Vec<Vec<u8>>);
+///
+/// impl MultipartHandler for Fields {
+///     fn on_part_begin(&mut self) -> bool {
+///         self.0.push(Vec::new());
+///         true
+///     }
+///
+///     fn on_part_data(&mut self, data: &[u8]) -> bool {
+///         self.0.last_mut().unwrap().extend_from_slice(data);
+///         true
+///     }
+/// }
+///
+/// let mut fields = Fields::default();
+/// let mut parser = MultipartParser::new(b"XyZ");
+///
+/// parser.parse(&mut fields, b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nfirst\r\n--X").unwrap();
+/// parser.parse(&mut fields, b"yZ\r\n\r\nsecond\r\n--XyZ--\r\n").unwrap();
+/// parser.finish().unwrap();
+///
+/// assert_eq!(fields.0, vec![b"first".to_vec(), b"second".to_vec()]);
+/// ```
+#[derive(Debug)]
+pub struct MultipartParser {
+    /// `CRLF--boundary`.
+    delimiter: Vec<u8>,
+    state: State,
+    /// Number of delimiter bytes matched at the end of the previous chunk.
+    matched: usize,
+    header_line: Vec<u8>,
+    headers_size: usize,
+    part_size: u64,
+    total_size: u64,
+    max_part_size: u64,
+    max_total_size: u64,
+}
+
+impl MultipartParser {
+    /// Creates a parser for a body with the given `boundary`, with no size limits.
+    pub fn new(boundary: &[u8]) -> MultipartParser {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary);
+
+        MultipartParser {
+            delimiter,
+            state: State::Preamble,
+            // The first boundary doesn't need to be preceded by a line break.
+            matched: 2,
+            header_line: Vec::new(),
+            headers_size: 0,
+            part_size: 0,
+            total_size: 0,
+            max_part_size: u64::MAX,
+            max_total_size: u64::MAX,
+        }
+    }
+
+    /// Sets the maximum size of a part's content in bytes.
+    pub fn max_part_size(mut self, max_part_size: u64) -> MultipartParser {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Sets the maximum size of the whole body in bytes.
+    pub fn max_total_size(mut self, max_total_size: u64) -> MultipartParser {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// Parses the next chunk of the body.
+    pub fn parse<H: MultipartHandler>(&mut self, handler: &mut H, mut data: &[u8]) -> Result<(), MultipartError> {
+        self.total_size += data.len() as u64;
+        if self.total_size > self.max_total_size {
+            return Err(MultipartError::BodyTooLarge);
+        }
+
+        while !data.is_empty() {
+            let consumed = match self.state {
+                State::Preamble | State::Data => self.parse_content(handler, data)?,
+                State::Headers => self.parse_headers(handler, data)?,
+                State::Epilogue => data.len(),
+                State::AfterBoundary | State::Closing | State::BoundaryLf => {
+                    self.parse_boundary_end(handler, data[0])?;
+                    1
+                }
+            };
+            data = &data[consumed..];
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the body has ended with the closing boundary.
+    pub fn finish(&self) -> Result<(), MultipartError> {
+        if self.state == State::Epilogue {
+            Ok(())
+        } else {
+            Err(MultipartError::Incomplete)
+        }
+    }
+
+    /// Searches for the delimiter, passing on part content or skipping the preamble.
+    /// Returns the number of bytes consumed.
+    fn parse_content<H: MultipartHandler>(&mut self, handler: &mut H, data: &[u8]) -> Result<usize, MultipartError> {
+        let delimiter_len = self.delimiter.len();
+
+        // Continue a match carried over from the previous chunk. The boundary can't
+        // contain CR, so after a mismatch a new match can only start in `data`.
+        if self.matched > 0 {
+            let matched = self.matched;
+            let len = (delimiter_len - matched).min(data.len());
+
+            if data[..len] == self.delimiter[matched..matched + len] {
+                self.matched += len;
+                if self.matched == delimiter_len {
+                    self.matched = 0;
+                    self.end_content(handler)?;
+                }
+                return Ok(len);
+            }
+
+            self.matched = 0;
+            if self.state == State::Data {
+                let carried = self.delimiter[..matched].to_vec();
+                self.part_data(handler, &carried)?;
+            }
+        }
+
+        if let Some(pos) = find(data, &self.delimiter) {
+            self.part_data(handler, &data[..pos])?;
+            self.end_content(handler)?;
+            return Ok(pos + delimiter_len);
+        }
+
+        // Hold back a trailing delimiter prefix, which can only start at the last CR.
+        let tail_start = data.len().saturating_sub(delimiter_len - 1);
+        let keep = match data[tail_start..].iter().rposition(|&b| b == b'\r') {
+            Some(cr) if self.delimiter.starts_with(&data[tail_start + cr..]) => data.len() - tail_start - cr,
+            _ => 0,
+        };
+
+        self.part_data(handler, &data[..data.len() - keep])?;
+        self.matched = keep;
+        Ok(data.len())
+    }
+
+    fn part_data<H: MultipartHandler>(&mut self, handler: &mut H, data: &[u8]) -> Result<(), MultipartError> {
+        if self.state != State::Data || data.is_empty() {
+            return Ok(());
+        }
+
+        self.part_size += data.len() as u64;
+        if self.part_size > self.max_part_size {
+            return Err(MultipartError::PartTooLarge);
+        }
+
+        call(handler.on_part_data(data))
+    }
+
+    fn end_content<H: MultipartHandler>(&mut self, handler: &mut H) -> Result<(), MultipartError> {
+        if self.state == State::Data {
+            call(handler.on_part_end())?;
+        }
+        self.state = State::AfterBoundary;
+        Ok(())
+    }
+
+    fn parse_boundary_end<H: MultipartHandler>(&mut self, handler: &mut H, byte: u8) -> Result<(), MultipartError> {
+        self.state = match (self.state, byte) {
+            (State::AfterBoundary, b'-') => State::Closing,
+            (State::AfterBoundary, b' ') | (State::AfterBoundary, b'\t') => State::AfterBoundary,
+            (State::AfterBoundary, b'\r') => State::BoundaryLf,
+            (State::Closing, b'-') => State::Epilogue,
+            (State::BoundaryLf, b'\n') => {
+                self.header_line.clear();
+                self.headers_size = 0;
+                self.part_size = 0;
+                call(handler.on_part_begin())?;
+                State::Headers
+            }
+            _ => return Err(MultipartError::InvalidHeader),
+        };
+        Ok(())
+    }
+
+    /// Reads part header lines. Returns the number of bytes consumed.
+    fn parse_headers<H: MultipartHandler>(&mut self, handler: &mut H, data: &[u8]) -> Result<usize, MultipartError> {
+        let (line, consumed) = match data.iter().position(|&b| b == b'\n') {
+            Some(lf) => (&data[..lf + 1], lf + 1),
+            None => (data, data.len()),
+        };
+
+        self.headers_size += line.len();
+        if self.headers_size > MAX_PART_HEADERS_SIZE {
+            return Err(MultipartError::HeadersTooLarge);
+        }
+        self.header_line.extend_from_slice(line);
+
+        if !self.header_line.ends_with(b"\n") {
+            return Ok(consumed);
+        }
+
+        if !self.header_line.ends_with(b"\r\n") {
+            return Err(MultipartError::InvalidHeader);
+        }
+
+        let line_len = self.header_line.len() - 2;
+        if line_len == 0 {
+            self.state = State::Data;
+            return Ok(consumed);
+        }
+
+        {
+            let line = &self.header_line[..line_len];
+            let colon = line.iter().position(|&b| b == b':').ok_or(MultipartError::InvalidHeader)?;
+            call(handler.on_part_header(trim(&line[..colon]), trim(&line[colon + 1..])))?;
+        }
+
+        self.header_line.clear();
+        Ok(consumed)
+    }
+}
+
+fn call(proceed: bool) -> Result<(), MultipartError> {
+    if proceed {
+        Ok(())
+    } else {
+        Err(MultipartError::Stopped)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let mut start = 0;
+
+    while let Some(offset) = haystack[start..].iter().position(|&b| b == needle[0]) {
+        let pos = start + offset;
+        if haystack[pos..].starts_with(needle) {
+            return Some(pos);
+        }
+        start = pos + 1;
+    }
+
+    None
+}
+
+/// A handler adapter parsing `multipart/form-data` request bodies.
+///
+/// The boundary is taken from the `Content-Type` header, and the body is
+/// passed to the inner handler as `MultipartHandler` events instead of
+/// `on_body` calls. Other bodies, and all other callbacks, are passed through.
+///
+/// On errors in the body the parser is stopped, and the error is available from `error`.
+pub struct Multipart<H> {
+    inner: H,
+    headers: HeaderWatch,
+    parser: Option<MultipartParser>,
+    max_part_size: u64,
+    max_total_size: u64,
+    error: Option<MultipartError>,
+}
+
+impl<H: ParserHandler + MultipartHandler> Multipart<H> {
+    /// Wraps `inner`, with no size limits.
+    pub fn new(inner: H) -> Multipart<H> {
+        Multipart {
+            inner,
+            headers: HeaderWatch::new("Content-Type"),
+            parser: None,
+            max_part_size: u64::MAX,
+            max_total_size: u64::MAX,
+            error: None,
+        }
+    }
+
+    /// Sets the maximum size of a part's content in bytes.
+    pub fn max_part_size(mut self, max_part_size: u64) -> Multipart<H> {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Sets the maximum size of the whole body in bytes.
+    pub fn max_total_size(mut self, max_total_size: u64) -> Multipart<H> {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    inner_accessors!(H);
+
+    /// Returns the error that stopped the parser, if the body was invalid.
+    pub fn error(&self) -> Option<&MultipartError> {
+        self.error.as_ref()
+    }
+
+    fn check(&mut self, result: Result<(), MultipartError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+}
+
+impl<H: ParserHandler + MultipartHandler> ParserHandler for Multipart<H> {
+    fn on_message_begin(&mut self, parser: &mut Parser) -> bool {
+        self.headers.clear();
+        self.parser = None;
+        self.error = None;
+        self.inner.on_message_begin(parser)
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.finish();
+
+        self.parser = self.headers.last().and_then(boundary).map(|boundary| {
+            MultipartParser::new(&boundary)
+                .max_part_size(self.max_part_size)
+                .max_total_size(self.max_total_size)
+        });
+
+        self.inner.on_headers_complete(parser)
+    }
+
+    fn on_body(&mut self, parser: &mut Parser, data: &[u8]) -> bool {
+        let result = match self.parser {
+            Some(ref mut multipart) => multipart.parse(&mut self.inner, data),
+            None => return self.inner.on_body(parser, data),
+        };
+
+        self.check(result)
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        let result = match self.parser.take() {
+            Some(multipart) => multipart.finish(),
+            None => Ok(()),
+        };
+
+        self.check(result) && self.inner.on_message_complete(parser)
+    }
+
+    pass_through!(on_url, on_status, on_header_field, on_header_value, on_chunk_header, on_chunk_complete);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{boundary, Multipart, MultipartError, MultipartHandler, MultipartParser};
+    use {Parser, ParserHandler};
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Part {
+        headers: Vec<(Vec<u8>, Vec<u8>)>,
+        data: Vec<u8>,
+        complete: bool,
+    }
+
+    #[derive(Default)]
+    struct Parts(Vec<Part>);
+
+    impl MultipartHandler for Parts {
+        fn on_part_begin(&mut self) -> bool {
+            self.0.push(Part::default());
+            true
+        }
+
+        fn on_part_header(&mut self, name: &[u8], value: &[u8]) -> bool {
+            self.0.last_mut().unwrap().headers.push((name.to_vec(), value.to_vec()));
+            true
+        }
+
+        fn on_part_data(&mut self, data: &[u8]) -> bool {
+            self.0.last_mut().unwrap().data.extend_from_slice(data);
+            true
+        }
+
+        fn on_part_end(&mut self) -> bool {
+            self.0.last_mut().unwrap().complete = true;
+            true
+        }
+    }
+
+    impl ParserHandler for Parts {}
+
+    const BODY: &[u8] = b"preamble\r\n\
+                          --b0undary\r\n\
+                          Content-Disposition: form-data; name=\"field\"\r\n\
+                          \r\n\
+                          value\r\n\
+                          --b0undary  \r\n\
+                          Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                          Content-Type: text/plain\r\n\
+                          \r\n\
+                          line 1\r\n-- b0undary\r\n\r\r\n--b0und\r\n\
+                          --b0undary--\r\n\
+                          epilogue";
+
+    fn expected() -> Vec<Part> {
+        vec![Part {
+                 headers: vec![(b"Content-Disposition".to_vec(), b"form-data; name=\"field\"".to_vec())],
+                 data: b"value".to_vec(),
+                 complete: true,
+             },
+             Part {
+                 headers: vec![(b"Content-Disposition".to_vec(),
+                                b"form-data; name=\"file\"; filename=\"a.txt\"".to_vec()),
+                               (b"Content-Type".to_vec(), b"text/plain".to_vec())],
+                 data: b"line 1\r\n-- b0undary\r\n\r\r\n--b0und".to_vec(),
+                 complete: true,
+             }]
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(boundary(b"Multipart/Form-Data; charset=utf-8; Boundary=abc"), Some(b"abc".to_vec()));
+        assert_eq!(boundary(b"multipart/form-data"), None);
+        assert_eq!(boundary(b"multipart/mixed; boundary=abc"), None);
+    }
+
+    #[test]
+    fn test_parts_split_at_every_byte() {
+        for split in 0..BODY.len() {
+            let mut parts = Parts::default();
+            let mut parser = MultipartParser::new(b"b0undary");
+
+            parser.parse(&mut parts, &BODY[..split]).unwrap();
+            parser.parse(&mut parts, &BODY[split..]).unwrap();
+            parser.finish().unwrap();
+
+            assert_eq!(parts.0, expected(), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let mut parts = Parts::default();
+        let mut parser = MultipartParser::new(b"b0undary");
+
+        for byte in BODY.chunks(1) {
+            parser.parse(&mut parts, byte).unwrap();
+        }
+        parser.finish().unwrap();
+
+        assert_eq!(parts.0, expected());
+    }
+
+    #[test]
+    fn test_limits_and_errors() {
+        let mut parser = MultipartParser::new(b"b0undary").max_part_size(5);
+        assert_eq!(parser.parse(&mut Parts::default(), BODY), Err(MultipartError::PartTooLarge));
+
+        let mut parser = MultipartParser::new(b"b0undary").max_total_size(10);
+        assert_eq!(parser.parse(&mut Parts::default(), BODY), Err(MultipartError::BodyTooLarge));
+
+        let mut parser = MultipartParser::new(b"b0undary");
+        parser.parse(&mut Parts::default(), &BODY[..50]).unwrap();
+        assert_eq!(parser.finish(), Err(MultipartError::Incomplete));
+
+        let mut parser = MultipartParser::new(b"b0undary");
+        assert_eq!(parser.parse(&mut Parts::default(), b"--b0undary\r\nno colon\r\n\r\n"),
+                   Err(MultipartError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_adapter() {
+        let mut req = format!("POST /upload HTTP/1.1\r\n\
+                               Content-Type: multipart/form-data; boundary=b0undary\r\n\
+                               Content-Length: {}\r\n\r\n",
+                              BODY.len())
+            .into_bytes();
+        req.extend_from_slice(BODY);
+
+        let mut handler = Multipart::new(Parts::default());
+        let mut parser = Parser::request();
+        for chunk in req.chunks(10) {
+            parser.parse(&mut handler, chunk);
+        }
+
+        assert!(!parser.has_error());
+        assert!(handler.error().is_none());
+        assert_eq!(handler.inner().0, expected());
+
+        let mut handler = Multipart::new(Parts::default()).max_part_size(5);
+        let mut parser = Parser::request();
+        parser.parse(&mut handler, &req);
+
+        assert_eq!(parser.error(), "HPE_CB_body");
+        assert_eq!(handler.error(), Some(&MultipartError::PartTooLarge));
+    }
+}
@@ -0,0 +1,321 @@
+//! Splitting a stream of pipelined messages into separate messages.
+
+use std::ops::Range;
+
+use error::HttpError;
+use {Parser, ParserHandler};
+
+/// A message read by a `Pipeline`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Offsets of the message's first and past-the-end bytes in the stream,
+    /// counting from the first byte passed to the pipeline.
+    pub range: Range<u64>,
+    /// `false` if this is the last message on the connection.
+    pub keep_alive: bool,
+    /// `true` if the connection switches to another protocol after this message.
+    pub upgrade: bool,
+}
+
+/// Pauses the parser at the end of each message.
+#[derive(Debug)]
+struct Stage<H> {
+    inner: H,
+    complete: Option<(bool, bool)>,
+}
+
+impl<H: ParserHandler> ParserHandler for Stage<H> {
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        if !self.inner.on_message_complete(parser) {
+            return false;
+        }
+
+        self.complete = Some((parser.should_keep_alive(), parser.is_upgrade()));
+        parser.pause();
+        true
+    }
+
+    pass_through!(unwatched: on_message_begin, on_url, on_status, on_header_field, on_header_value, on_headers_complete,
+                  on_body, on_chunk_header, on_chunk_complete);
+}
+
+/// Reads pipelined messages from a connection one at a time.
+///
+/// Data is passed to `feed`, which returns an iterator over the messages
+/// completed in it, each with its position in the stream. The handler's
+/// callbacks for a message have all been called by the time it's returned.
+///
+/// A server has to answer pipelined requests in order, so to bound the work
+/// queued up by a client, the pipeline stops reading once `max_in_flight`
+/// requests (16 by default) have been read without `response_sent` being
+/// called for them. It also stops for good after a message that closes the
+/// connection or upgrades it to another protocol. In either case the iterator
+/// ends early: `Messages::consumed` tells how much of the data has been read,
+/// and the rest should be kept to be fed again later or, after an upgrade,
+/// passed on to the new protocol.
+///
+/// # Example
+/// ```
+/// use http_muncher::{Parser, ParserHandler, Pipeline};
+///
+/// struct Urls(Vec<Vec<u8>>);
+///
+/// impl ParserHandler for Urls {
+///     fn on_url(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+///         self.0.push(data.to_vec());
+///         true
+///     }
+/// }
+///
+/// let mut pipeline = Pipeline::new(Parser::request(), Urls(Vec::new()));
+/// let data = b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n";
+///
+/// let messages: Vec<_> = pipeline.feed(data).map(Result::unwrap).collect();
+/// assert_eq!(messages[0].range, 0..19);
+/// assert_eq!(messages[1].range, 19..data.len() as u64);
+/// assert!(!messages[1].keep_alive);
+/// assert!(pipeline.is_closed());
+/// ```
+#[derive(Debug)]
+pub struct Pipeline<H> {
+    parser: Parser,
+    handler: Stage<H>,
+    position: u64,
+    start: u64,
+    in_flight: usize,
+    max_in_flight: usize,
+    closed: bool,
+}
+
+impl<H: ParserHandler> Pipeline<H> {
+    /// Reads messages with `parser`, passing their callbacks on to `inner`.
+    pub fn new(parser: Parser, inner: H) -> Pipeline<H> {
+        Pipeline {
+            parser,
+            handler: Stage { inner, complete: None },
+            position: 0,
+            start: 0,
+            in_flight: 0,
+            max_in_flight: 16,
+            closed: false,
+        }
+    }
+
+    /// Sets the maximum number of messages read ahead of the responses sent.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Pipeline<H> {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Returns an iterator reading messages from `data`.
+    pub fn feed<'a>(&'a mut self, data: &'a [u8]) -> Messages<'a, H> {
+        Messages {
+            pipeline: self,
+            data,
+            consumed: 0,
+        }
+    }
+
+    /// Records that a response has been sent, making room for another message.
+    pub fn response_sent(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+
+    /// Returns the number of messages read for which no response has been sent yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Checks if the pipeline won't read further messages because of `max_in_flight`.
+    pub fn is_full(&self) -> bool {
+        self.in_flight >= self.max_in_flight
+    }
+
+    /// Checks if the connection has been closed, upgraded or has failed to
+    /// parse, so that no further messages will be read.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the number of bytes read from the stream so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the parser reading the messages.
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    /// Returns the parser reading the messages mutably.
+    pub fn parser_mut(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    inner_accessors!(H, handler.inner);
+}
+
+/// An iterator over the messages read from a buffer, returned by `Pipeline::feed`.
+///
+/// A parsing error is returned once, after which the pipeline is closed.
+#[derive(Debug)]
+pub struct Messages<'a, H: 'a> {
+    pipeline: &'a mut Pipeline<H>,
+    data: &'a [u8],
+    consumed: usize,
+}
+
+impl<'a, H: ParserHandler> Messages<'a, H> {
+    /// Returns the number of bytes read from the buffer so far.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+}
+
+impl<'a, H: ParserHandler> Iterator for Messages<'a, H> {
+    type Item = Result<Message, HttpError>;
+
+    fn next(&mut self) -> Option<Result<Message, HttpError>> {
+        let pipeline = &mut *self.pipeline;
+
+        // An empty buffer would tell http-parser that the connection has ended.
+        if self.data.is_empty() || pipeline.closed || pipeline.is_full() || pipeline.parser.is_paused() {
+            return None;
+        }
+
+        let size = pipeline.parser.parse(&mut pipeline.handler, self.data);
+        self.data = &self.data[size..];
+        self.consumed += size;
+        pipeline.position += size as u64;
+
+        if let Some((keep_alive, upgrade)) = pipeline.handler.complete.take() {
+            pipeline.parser.unpause();
+            pipeline.in_flight += 1;
+            pipeline.closed = !keep_alive || upgrade;
+
+            let range = pipeline.start..pipeline.position;
+            pipeline.start = pipeline.position;
+            return Some(Ok(Message { range, keep_alive, upgrade }));
+        }
+
+        if pipeline.parser.has_error() && !pipeline.parser.is_paused() {
+            pipeline.closed = true;
+            return Some(Err(HttpError::new(pipeline.parser.http_errnum())));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Pipeline};
+    use {Parser, ParserHandler};
+
+    #[derive(Default)]
+    struct Bodies(Vec<Vec<u8>>);
+
+    impl ParserHandler for Bodies {
+        fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+            self.0.push(Vec::new());
+            true
+        }
+
+        fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+            self.0.last_mut().unwrap().extend_from_slice(data);
+            true
+        }
+    }
+
+    const REQUESTS: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\none\
+                              POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\ntwo\r\n0\r\n\r\n\
+                              GET /c HTTP/1.1\r\n\r\n";
+
+    #[test]
+    fn test_messages_split_between_buffers() {
+        for split in 1..REQUESTS.len() {
+            let mut pipeline = Pipeline::new(Parser::request(), Bodies::default());
+            let mut messages = Vec::new();
+
+            for chunk in &[&REQUESTS[..split], &REQUESTS[split..]] {
+                let mut feed = pipeline.feed(chunk);
+                messages.extend(feed.by_ref().map(Result::unwrap));
+                assert_eq!(feed.consumed(), chunk.len(), "split at {}", split);
+            }
+
+            let ranges: Vec<_> = messages.iter().map(|message| message.range.clone()).collect();
+            assert_eq!(ranges, vec![0..42, 42..103, 103..122], "split at {}", split);
+            assert!(messages.iter().all(|message| message.keep_alive && !message.upgrade));
+            assert_eq!(pipeline.in_flight(), 3);
+            assert_eq!(pipeline.inner().0, vec![b"one".to_vec(), b"two".to_vec(), vec![]]);
+        }
+    }
+
+    #[test]
+    fn test_feeding_again_after_a_message() {
+        let mut pipeline = Pipeline::new(Parser::request(), Bodies::default());
+
+        for (i, request) in [&b"GET /a HTTP/1.1\r\n\r\n"[..], &b"GET /b HTTP/1.1\r\n\r\n"[..]].iter().enumerate() {
+            let messages: Vec<_> = pipeline.feed(request).map(Result::unwrap).collect();
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].range, 19 * i as u64..19 * (i as u64 + 1));
+            assert!(!pipeline.parser().is_paused());
+        }
+        assert_eq!(pipeline.in_flight(), 2);
+    }
+
+    #[test]
+    fn test_max_in_flight() {
+        let mut pipeline = Pipeline::new(Parser::request(), Bodies::default()).max_in_flight(2);
+
+        let consumed = {
+            let mut feed = pipeline.feed(REQUESTS);
+            assert_eq!(feed.by_ref().count(), 2);
+            feed.consumed()
+        };
+        assert_eq!(consumed, 103);
+        assert!(pipeline.is_full());
+        assert_eq!(pipeline.feed(&REQUESTS[consumed..]).count(), 0);
+
+        pipeline.response_sent();
+        let message = pipeline.feed(&REQUESTS[consumed..]).next().unwrap().unwrap();
+        assert_eq!(message.range, 103..122);
+    }
+
+    #[test]
+    fn test_stops_at_connection_close_and_upgrade() {
+        let data = b"GET /a HTTP/1.1\r\nConnection: close\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+        let mut pipeline = Pipeline::new(Parser::request(), Bodies::default());
+
+        let (messages, consumed) = {
+            let mut feed = pipeline.feed(data);
+            (feed.by_ref().collect::<Vec<_>>(), feed.consumed())
+        };
+        assert_eq!(messages, vec![Ok(Message { range: 0..38, keep_alive: false, upgrade: false })]);
+        assert_eq!(consumed, 38);
+        assert!(pipeline.is_closed());
+
+        let data = b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n\x81\x00";
+        let mut pipeline = Pipeline::new(Parser::request(), Bodies::default());
+
+        let (messages, consumed) = {
+            let mut feed = pipeline.feed(data);
+            (feed.by_ref().collect::<Vec<_>>(), feed.consumed())
+        };
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].as_ref().unwrap().upgrade);
+        assert_eq!(&data[consumed..], b"\x81\x00");
+    }
+
+    #[test]
+    fn test_error_closes_pipeline() {
+        let mut pipeline = Pipeline::new(Parser::request(), Bodies::default());
+        let data = b"GET /a HTTP/1.1\r\n\r\nXYZ / HTTP/1.1\r\n\r\n";
+
+        let results: Vec<_> = pipeline.feed(data).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().name(), "HPE_INVALID_METHOD");
+        assert!(pipeline.is_closed());
+    }
+}
@@ -0,0 +1,106 @@
+//! Recycling parsers between connections.
+
+use {Parser, ParserType};
+
+/// A pool of idle parsers for servers that recycle per-connection state.
+///
+/// Parsers taken from the pool are reset, so they never carry state over from
+/// a previous connection.
+///
+/// # Example
+/// ```
+/// use http_muncher::{ParserPool, ParserType};
+///
+/// let mut pool = ParserPool::new(128);
+///
+/// let parser = pool.take(ParserType::HttpRequest);
+/// // ... parse the connection's requests ...
+/// pool.put(parser);
+///
+/// assert_eq!(pool.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct ParserPool {
+    idle: Vec<Parser>,
+    max_idle: usize,
+}
+
+impl ParserPool {
+    /// Creates an empty pool keeping at most `max_idle` parsers around.
+    pub fn new(max_idle: usize) -> ParserPool {
+        ParserPool {
+            idle: Vec::new(),
+            max_idle,
+        }
+    }
+
+    /// Takes an idle parser reset to handle `parser_type` messages, or creates a new one.
+    pub fn take(&mut self, parser_type: ParserType) -> Parser {
+        match self.idle.pop() {
+            Some(mut parser) => {
+                parser.reset_as(parser_type);
+                parser
+            }
+            None => match parser_type {
+                ParserType::HttpRequest => Parser::request(),
+                ParserType::HttpResponse => Parser::response(),
+                ParserType::HttpBoth => Parser::request_and_response(),
+            },
+        }
+    }
+
+    /// Returns a parser to the pool, dropping it if the pool is full.
+    pub fn put(&mut self, parser: Parser) {
+        if self.idle.len() < self.max_idle {
+            self.idle.push(parser);
+        }
+    }
+
+    /// Returns the number of idle parsers.
+    pub fn len(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Checks if there are no idle parsers.
+    pub fn is_empty(&self) -> bool {
+        self.idle.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParserPool;
+    use {Parser, ParserHandler, ParserType};
+
+    struct NoopHandler;
+    impl ParserHandler for NoopHandler {}
+
+    #[test]
+    fn test_recycled_parser_is_reset() {
+        let mut pool = ParserPool::new(1);
+
+        let mut parser = pool.take(ParserType::HttpRequest);
+        parser.parse(&mut NoopHandler, b"GARBAGE");
+        assert!(parser.has_error());
+        pool.put(parser);
+
+        let mut parser = pool.take(ParserType::HttpResponse);
+        assert!(pool.is_empty());
+        assert!(!parser.has_error());
+        assert_eq!(parser.parser_type(), ParserType::HttpResponse);
+
+        parser.parse(&mut NoopHandler, b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert!(!parser.has_error());
+        assert_eq!(parser.status_code(), 204);
+    }
+
+    #[test]
+    fn test_pool_is_bounded() {
+        let mut pool = ParserPool::new(1);
+
+        pool.put(Parser::request());
+        pool.put(Parser::request());
+
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,523 @@
+//! Forwarding requests and responses between a client and an upstream server.
+//!
+//! A `Proxy` reads requests from a client connection, forwards them to an
+//! upstream connection with hop-by-hop headers removed and `Forwarded` and
+//! `X-Forwarded-For` added, and forwards the responses back the same way.
+//! Bodies are streamed rather than buffered, and re-chunked when the framing
+//! on the two sides differs. Any `Read` and `Write` will do for either side.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::{TcpListener, TcpStream};
+//! use http_muncher::proxy::Proxy;
+//!
+//! let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+//! let (mut client, addr) = listener.accept().unwrap();
+//! let mut proxy = Proxy::new(addr.ip());
+//! let mut upstream = TcpStream::connect("127.0.0.1:9000").unwrap();
+//!
+//! while let Some(head) = proxy.read_request(&mut client).unwrap() {
+//!     if !proxy.upstream_keep_alive() {
+//!         upstream = TcpStream::connect("127.0.0.1:9000").unwrap();
+//!     }
+//!     proxy.forward_request(&head, &mut client, &mut upstream).unwrap();
+//!     proxy.forward_response(&mut upstream, &mut client).unwrap();
+//! }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::str;
+
+use headers::{self, Headers};
+use writer;
+use {ClientConnection, ClientEvent, ConnectionError, RequestHead, ResponseHead, ServerConnection, ServerEvent};
+
+/// An error returned by `Proxy`.
+///
+/// After an error both connections should be closed, after answering the
+/// client with `400 Bad Request` or `502 Bad Gateway` where it still can be.
+#[derive(Debug)]
+pub enum ProxyError {
+    /// Reading from or writing to either connection failed.
+    Io(io::Error),
+    /// The client's request couldn't be parsed, or the proxy was used out of order.
+    Client(ConnectionError),
+    /// The upstream response couldn't be parsed, or the request couldn't be serialized for it.
+    Upstream(ConnectionError),
+    /// The client sent a `CONNECT` request, which can't be forwarded.
+    Unsupported,
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProxyError::Io(ref err) => write!(f, "I/O error: {}", err),
+            ProxyError::Client(ref err) => write!(f, "client connection: {}", err),
+            ProxyError::Upstream(ref err) => write!(f, "upstream connection: {}", err),
+            ProxyError::Unsupported => f.write_str("CONNECT requests can't be forwarded"),
+        }
+    }
+}
+
+impl Error for ProxyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ProxyError::Io(ref err) => Some(err),
+            ProxyError::Client(ref err) | ProxyError::Upstream(ref err) => Some(err),
+            ProxyError::Unsupported => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(err: io::Error) -> ProxyError {
+        ProxyError::Io(err)
+    }
+}
+
+/// Headers that only apply to a single connection, along with the framing
+/// headers, which the proxy sets itself.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+fn is_hop_by_hop(name: &[u8]) -> bool {
+    HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop.as_bytes())) ||
+        name.len() >= 6 && name[..6].eq_ignore_ascii_case(b"proxy-")
+}
+
+/// Returns the headers to pass on, leaving out hop-by-hop headers, headers
+/// prefixed with `Proxy-` and headers listed in `Connection`.
+fn end_to_end(headers: &Headers) -> Vec<(&[u8], &[u8])> {
+    let listed: Vec<&[u8]> = headers.get_all("Connection")
+        .filter_map(|value| headers::connection(value).ok())
+        .flatten()
+        .collect();
+
+    headers.iter()
+        .filter(|&(name, _)| !is_hop_by_hop(name) && !listed.iter().any(|option| name.eq_ignore_ascii_case(option)))
+        .collect()
+}
+
+/// Returns all values of a header joined into one, or `None` if it's missing.
+fn joined(headers: &Headers, name: &str) -> Option<Vec<u8>> {
+    let values: Vec<&[u8]> = headers.get_all(name).collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(&b", "[..]))
+    }
+}
+
+/// Returns a `Forwarded` element describing a request from `client_addr`.
+fn forwarded_element(client_addr: IpAddr, host: Option<&[u8]>, proto: &str) -> Vec<u8> {
+    let mut element = match client_addr {
+        IpAddr::V4(ip) => format!("for={}", ip),
+        IpAddr::V6(ip) => format!("for=\"[{}]\"", ip),
+    }.into_bytes();
+
+    if let Some(host) = host {
+        element.extend_from_slice(b";host=");
+        if headers::is_token(host) {
+            element.extend_from_slice(host);
+        } else {
+            element.push(b'"');
+            for &b in host {
+                if b == b'"' || b == b'\\' {
+                    element.push(b'\\');
+                }
+                element.push(b);
+            }
+            element.push(b'"');
+        }
+    }
+
+    element.extend_from_slice(b";proto=");
+    element.extend_from_slice(proto.as_bytes());
+    element
+}
+
+fn read<R: Read>(stream: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match stream.read(buf) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+/// Forwards requests from a client connection to an upstream server, and
+/// the responses back to the client.
+///
+/// Each request is read with `read_request`, which returns its head so that
+/// an upstream can be picked for it, or `None` once the client is done. The
+/// head, possibly modified, is then passed to `forward_request`, which sends
+/// it upstream followed by the body, and `forward_response` sends the
+/// response back.
+///
+/// `Connection`, `Keep-Alive`, `TE`, `Trailer`, `Upgrade`, headers prefixed
+/// with `Proxy-` and headers listed in `Connection` aren't forwarded in either
+/// direction, and neither is `Expect`: a client that waits for `100 Continue`
+/// has to be answered with `writer::write_continue` before the request is
+/// forwarded. The client's address is appended to `Forwarded` and
+/// `X-Forwarded-For`, so only their last entries can be trusted.
+///
+/// Chunked bodies are forwarded chunked, with extensions and trailers
+/// dropped. A response body without a length, read until the upstream closes
+/// the connection, is chunked for HTTP/1.1 clients, and for HTTP/1.0 clients
+/// it's ended by closing the client connection.
+#[derive(Debug)]
+pub struct Proxy {
+    client: ServerConnection,
+    upstream: ClientConnection,
+    client_addr: IpAddr,
+    proto: &'static str,
+    /// The method and version of the request being forwarded.
+    request: Option<(&'static str, (u16, u16))>,
+    upstream_keep_alive: bool,
+}
+
+impl Proxy {
+    /// Creates a proxy for a client connecting from `client_addr`.
+    pub fn new(client_addr: IpAddr) -> Proxy {
+        Proxy {
+            client: ServerConnection::new(),
+            upstream: ClientConnection::new(),
+            client_addr,
+            proto: "http",
+            request: None,
+            upstream_keep_alive: true,
+        }
+    }
+
+    /// Sets the protocol the client connected with, `http` by default, for the `Forwarded` header.
+    pub fn proto(mut self, proto: &'static str) -> Proxy {
+        self.proto = proto;
+        self
+    }
+
+    /// Reads the head of the next request from the client, or returns `None`
+    /// if the client has closed the connection or asked for it to be closed.
+    pub fn read_request<R: Read>(&mut self, client: &mut R) -> Result<Option<RequestHead>, ProxyError> {
+        let mut buf = [0; 8192];
+
+        loop {
+            match self.client.next_event().map_err(ProxyError::Client)? {
+                ServerEvent::RequestHead(head) => {
+                    if head.method == "CONNECT" {
+                        return Err(ProxyError::Unsupported);
+                    }
+                    self.request = Some((head.method, head.version));
+                    return Ok(Some(head));
+                }
+                ServerEvent::NeedMoreData => {
+                    let size = read(client, &mut buf)?;
+                    self.client.receive(&buf[..size]);
+                }
+                ServerEvent::Close => return Ok(None),
+                _ => return Err(ProxyError::Client(ConnectionError::State("the previous request hasn't been forwarded"))),
+            }
+        }
+    }
+
+    /// Sends `head` upstream, followed by the request body read from the client.
+    pub fn forward_request<R: Read, W: Write>(&mut self, head: &RequestHead, client: &mut R, upstream: &mut W) -> Result<(), ProxyError> {
+        let target = str::from_utf8(&head.url)
+            .map_err(|_| ProxyError::Client(ConnectionError::State("the request target isn't UTF-8")))?;
+
+        let mut forwarded = joined(&head.headers, "Forwarded").map(|value| [value, b", ".to_vec()].concat()).unwrap_or_default();
+        forwarded.extend(forwarded_element(self.client_addr, head.headers.get("Host"), self.proto));
+        let mut forwarded_for = joined(&head.headers, "X-Forwarded-For").map(|value| [value, b", ".to_vec()].concat()).unwrap_or_default();
+        forwarded_for.extend(self.client_addr.to_string().into_bytes());
+
+        let transfer_encoding = joined(&head.headers, "Transfer-Encoding");
+        let length = head.headers.content_length().and_then(Result::ok).map(|length| length.to_string());
+
+        let mut headers = end_to_end(&head.headers);
+        headers.retain(|&(name, _)| {
+            !name.eq_ignore_ascii_case(b"expect") &&
+                !name.eq_ignore_ascii_case(b"forwarded") &&
+                !name.eq_ignore_ascii_case(b"x-forwarded-for")
+        });
+        headers.push((b"Forwarded", &forwarded));
+        headers.push((b"X-Forwarded-For", &forwarded_for));
+        if let Some(ref transfer_encoding) = transfer_encoding {
+            headers.push((b"Transfer-Encoding", transfer_encoding));
+        } else if let Some(ref length) = length {
+            headers.push((b"Content-Length", length.as_bytes()));
+        }
+
+        let out = self.upstream.send_request(head.method, target, headers).map_err(ProxyError::Upstream)?;
+        upstream.write_all(&out)?;
+
+        let mut buf = [0; 8192];
+        loop {
+            match self.client.next_event().map_err(ProxyError::Client)? {
+                ServerEvent::Body(data) => {
+                    let out = self.upstream.send_data(&data).map_err(ProxyError::Upstream)?;
+                    upstream.write_all(&out)?;
+                }
+                ServerEvent::RequestEnd => {
+                    let out = self.upstream.end_request().map_err(ProxyError::Upstream)?;
+                    upstream.write_all(&out)?;
+                    upstream.flush()?;
+                    return Ok(());
+                }
+                ServerEvent::NeedMoreData => {
+                    let size = read(client, &mut buf)?;
+                    self.client.receive(&buf[..size]);
+                }
+                _ => return Err(ProxyError::Client(ConnectionError::State("no request head has been read"))),
+            }
+        }
+    }
+
+    /// Reads the response from upstream and sends it to the client, along
+    /// with any interim `1xx` responses before it. Returns the upstream
+    /// response's head.
+    pub fn forward_response<R: Read, W: Write>(&mut self, upstream: &mut R, client: &mut W) -> Result<ResponseHead, ProxyError> {
+        let (method, version) = self.request
+            .ok_or(ProxyError::Client(ConnectionError::State("no request has been forwarded")))?;
+        let mut response = None;
+        let mut buf = [0; 8192];
+
+        loop {
+            match self.upstream.next_event().map_err(ProxyError::Upstream)? {
+                ClientEvent::Informational { status, headers } => {
+                    // HTTP/1.0 clients don't expect interim responses, and
+                    // `100 Continue` answers an `Expect` that isn't forwarded.
+                    if version >= (1, 1) && status != 100 {
+                        let mut out = Vec::new();
+                        writer::write_response_head(&mut out, status, writer::reason_phrase(status), end_to_end(&headers))?;
+                        client.write_all(&out)?;
+                    }
+                }
+                ClientEvent::ResponseHead(head) => {
+                    let chunked = self.start_response(&head, method, version, client)?;
+                    response = Some((head, chunked));
+                }
+                ClientEvent::Body(data) => match response {
+                    Some((_, true)) => writer::write_chunk(client, &data)?,
+                    _ => client.write_all(&data)?,
+                },
+                ClientEvent::ResponseEnd => {
+                    if let Some((head, chunked)) = response.take() {
+                        if chunked {
+                            writer::write_last_chunk(client)?;
+                        }
+                        client.flush()?;
+                        self.client.end_response().map_err(ProxyError::Client)?;
+                        self.request = None;
+
+                        self.upstream_keep_alive = self.upstream.keep_alive();
+                        if !self.upstream_keep_alive {
+                            self.upstream = ClientConnection::new();
+                        }
+                        return Ok(head);
+                    }
+                }
+                ClientEvent::NeedMoreData => {
+                    let size = read(upstream, &mut buf)?;
+                    self.upstream.receive(&buf[..size]);
+                }
+                ClientEvent::Paused | ClientEvent::Close | ClientEvent::Upgrade(_) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the upstream connection closed before the response").into());
+                }
+            }
+        }
+    }
+
+    /// Checks if the client connection stays open for another request.
+    pub fn keep_alive(&self) -> bool {
+        self.client.keep_alive()
+    }
+
+    /// Checks if the next request can be forwarded on the same upstream
+    /// connection as the last one. If not, it has to go to a new connection.
+    pub fn upstream_keep_alive(&self) -> bool {
+        self.upstream_keep_alive
+    }
+
+    /// Writes the head of the response to the client, and returns whether its body has to be chunked.
+    fn start_response<W: Write>(&mut self, head: &ResponseHead, method: &str, version: (u16, u16), client: &mut W) -> Result<bool, ProxyError> {
+        let mut transfer_encoding = joined(&head.headers, "Transfer-Encoding");
+        let length = head.headers.content_length().and_then(Result::ok).map(|length| length.to_string());
+
+        let bodiless = method == "HEAD" || head.status == 204 || head.status == 304;
+        let delimited = transfer_encoding.is_none() && length.is_some();
+        let chunked = !bodiless && !delimited && version >= (1, 1);
+        let keep_alive = self.client.keep_alive() && (bodiless || delimited || chunked);
+
+        // A body read until the upstream closes is sent chunked, so `chunked`
+        // has to become its last coding.
+        let last_chunked = head.headers.transfer_encoding().and_then(Result::ok)
+            .is_some_and(|codings| codings.last().is_some_and(|coding| coding.eq_ignore_ascii_case(b"chunked")));
+        if chunked && !last_chunked {
+            if let Some(ref mut value) = transfer_encoding {
+                value.extend_from_slice(b", chunked");
+            }
+        }
+
+        let mut headers = end_to_end(&head.headers);
+        if bodiless {
+            // Responses to `HEAD` describe the body they'd have.
+            headers.extend(head.headers.iter().filter(|&(name, _)| {
+                name.eq_ignore_ascii_case(b"transfer-encoding") || name.eq_ignore_ascii_case(b"content-length")
+            }));
+        } else if chunked {
+            headers.push((b"Transfer-Encoding", transfer_encoding.as_ref().map_or(&b"chunked"[..], |value| &value[..])));
+        } else if let Some(ref length) = length {
+            headers.push((b"Content-Length", length.as_bytes()));
+        }
+        if !keep_alive {
+            headers.push((b"Connection", b"close"));
+        } else if version < (1, 1) {
+            headers.push((b"Connection", b"keep-alive"));
+        }
+
+        let reason = str::from_utf8(&head.reason).unwrap_or_else(|_| writer::reason_phrase(head.status));
+        let mut out = Vec::new();
+        writer::write_response_head(&mut out, head.status, reason, headers)?;
+
+        self.client.start_response(head.status, keep_alive).map_err(ProxyError::Client)?;
+        client.write_all(&out)?;
+        Ok(chunked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{end_to_end, forwarded_element, Proxy, ProxyError};
+    use headers::Headers;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn headers(list: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in list {
+            headers.on_header_field(name.as_bytes());
+            headers.on_header_value(value.as_bytes());
+        }
+        headers.on_headers_complete();
+        headers
+    }
+
+    #[test]
+    fn test_end_to_end_headers() {
+        let headers = headers(&[
+            ("Host", "example.com"),
+            ("Connection", "keep-alive, X-Hop"),
+            ("x-hop", "1"),
+            ("Keep-Alive", "timeout=5"),
+            ("TE", "trailers"),
+            ("Upgrade", "h2c"),
+            ("Proxy-Authorization", "Basic eDp5"),
+            ("Content-Length", "3"),
+            ("Accept", "*/*"),
+        ]);
+
+        assert_eq!(end_to_end(&headers), vec![(&b"Host"[..], &b"example.com"[..]), (&b"Accept"[..], &b"*/*"[..])]);
+    }
+
+    #[test]
+    fn test_forwarded_element() {
+        assert_eq!(forwarded_element(CLIENT, Some(b"example.com"), "http"), b"for=192.0.2.1;host=example.com;proto=http");
+        assert_eq!(forwarded_element(IpAddr::V6(Ipv6Addr::LOCALHOST), Some(b"example.com:8080"), "https"),
+                   &b"for=\"[::1]\";host=\"example.com:8080\";proto=https"[..]);
+        assert_eq!(forwarded_element(CLIENT, None, "http"), b"for=192.0.2.1;proto=http");
+    }
+
+    #[test]
+    fn test_forward_request() {
+        let mut client = Cursor::new(&b"POST /api HTTP/1.1\r\nHost: example.com\r\nConnection: X-Hop\r\nX-Hop: 1\r\n\
+                                        Expect: 100-continue\r\nForwarded: for=10.0.0.1\r\nX-Forwarded-For: 10.0.0.1\r\n\
+                                        Transfer-Encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: x\r\n\r\n"[..]);
+        let mut upstream = Vec::new();
+        let mut proxy = Proxy::new(CLIENT);
+
+        let head = proxy.read_request(&mut client).unwrap().unwrap();
+        assert_eq!(head.url, b"/api");
+        proxy.forward_request(&head, &mut client, &mut upstream).unwrap();
+
+        assert_eq!(String::from_utf8(upstream).unwrap(),
+                   "POST /api HTTP/1.1\r\nHost: example.com\r\n\
+                    Forwarded: for=10.0.0.1, for=192.0.2.1;host=example.com;proto=http\r\n\
+                    X-Forwarded-For: 10.0.0.1, 192.0.2.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_forward_responses() {
+        let mut client = Cursor::new(&b"GET /a HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                                        GET /b HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                                        HEAD /c HTTP/1.1\r\n\r\n\
+                                        GET /d HTTP/1.0\r\n\r\n\
+                                        GET /ignored HTTP/1.1\r\n\r\n"[..]);
+        let responses: &[&[u8]] = &[
+            b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\n\r\nuntil eof",
+            b"HTTP/1.1 200 OK\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n\r\nok",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n",
+            b"HTTP/1.1 200 Fine\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n",
+        ];
+        let mut output = Vec::new();
+        let mut proxy = Proxy::new(CLIENT);
+        let mut keep_alive = Vec::new();
+
+        for response in responses {
+            let head = proxy.read_request(&mut client).unwrap().unwrap();
+            proxy.forward_request(&head, &mut client, &mut Vec::new()).unwrap();
+            proxy.forward_response(&mut Cursor::new(response), &mut output).unwrap();
+            keep_alive.push((proxy.keep_alive(), proxy.upstream_keep_alive()));
+        }
+        assert!(proxy.read_request(&mut client).unwrap().is_none());
+
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n9\r\nuntil eof\r\n0\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\nok\
+                    HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n\
+                    HTTP/1.1 200 Fine\r\nConnection: close\r\n\r\nhi");
+        assert_eq!(keep_alive, vec![(true, false), (true, true), (true, true), (false, true)]);
+    }
+
+    #[test]
+    fn test_rechunked_transfer_encoding() {
+        let mut client = Cursor::new(&b"GET / HTTP/1.1\r\n\r\n"[..]);
+        let mut output = Vec::new();
+        let mut proxy = Proxy::new(CLIENT);
+
+        let head = proxy.read_request(&mut client).unwrap().unwrap();
+        proxy.forward_request(&head, &mut client, &mut Vec::new()).unwrap();
+        let response = &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\nuntil eof"[..];
+        proxy.forward_response(&mut Cursor::new(response), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n9\r\nuntil eof\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut proxy = Proxy::new(CLIENT);
+        let mut client = Cursor::new(&b"CONNECT example.com:443 HTTP/1.1\r\n\r\n"[..]);
+        assert!(matches!(proxy.read_request(&mut client), Err(ProxyError::Unsupported)));
+
+        let mut proxy = Proxy::new(CLIENT);
+        let mut client = Cursor::new(&b"GET / HTTP/1.1\r\n\r\n"[..]);
+        let head = proxy.read_request(&mut client).unwrap().unwrap();
+        proxy.forward_request(&head, &mut client, &mut Vec::new()).unwrap();
+        let result = proxy.forward_response(&mut Cursor::new(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nab"[..]), &mut Vec::new());
+        assert!(matches!(result, Err(ProxyError::Upstream(_))));
+    }
+}
@@ -0,0 +1,172 @@
+//! Query string parsing for URLs received in `on_url`.
+//!
+//! http-parser may deliver a URL in several `on_url` fragments, so collect them
+//! into one buffer first and parse the complete URL (or query string) afterwards.
+//!
+//! # Example
+//! ```
+//! use http_muncher::query;
+//!
+//! let pairs: Vec<_> = query::from_url(b"/search?q=rust+http&page=2").collect();
+//!
+//! assert_eq!(pairs[0], (b"q"[..].into(), b"rust http"[..].into()));
+//! assert_eq!(pairs[1], (b"page"[..].into(), b"2"[..].into()));
+//! ```
+
+use std::borrow::Cow;
+
+/// Returns the query string of `url`, without the leading `?` and the fragment.
+///
+/// Returns an empty slice if there's no query string.
+pub fn query_string(url: &[u8]) -> &[u8] {
+    let url = match url.iter().position(|&b| b == b'#') {
+        Some(pos) => &url[..pos],
+        None => url,
+    };
+
+    match url.iter().position(|&b| b == b'?') {
+        Some(pos) => &url[pos + 1..],
+        None => &[],
+    }
+}
+
+/// Parses the query string of `url` into decoded key-value pairs.
+pub fn from_url(url: &[u8]) -> Pairs<'_> {
+    parse(query_string(url))
+}
+
+/// Parses an `application/x-www-form-urlencoded` string into decoded key-value pairs.
+pub fn parse(input: &[u8]) -> Pairs<'_> {
+    Pairs { input }
+}
+
+/// An iterator over the key-value pairs of a query string.
+///
+/// Pairs are yielded in order, so repeated keys are yielded once per
+/// occurrence. A key without `=` gets an empty value, and empty pairs (as in
+/// `a=1&&b=2`) are skipped.
+#[derive(Debug, Clone)]
+pub struct Pairs<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Iterator for Pairs<'a> {
+    type Item = (Cow<'a, [u8]>, Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.input.is_empty() {
+            let pair = match self.input.iter().position(|&b| b == b'&') {
+                Some(pos) => {
+                    let pair = &self.input[..pos];
+                    self.input = &self.input[pos + 1..];
+                    pair
+                }
+                None => {
+                    let pair = self.input;
+                    self.input = &[];
+                    pair
+                }
+            };
+
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match pair.iter().position(|&b| b == b'=') {
+                Some(pos) => (&pair[..pos], &pair[pos + 1..]),
+                None => (pair, &[][..]),
+            };
+
+            return Some((decode(key), decode(value)));
+        }
+
+        None
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` data: `+` becomes a space and
+/// `%XX` escapes are decoded.
+///
+/// Malformed escapes (a `%` not followed by two hex digits) are kept as is.
+/// The input is borrowed if there's nothing to decode.
+pub fn decode(input: &[u8]) -> Cow<'_, [u8]> {
+    if !input.iter().any(|&b| b == b'+' || b == b'%') {
+        return Cow::Borrowed(input);
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'+' => output.push(b' '),
+            b'%' if i + 2 < input.len() => {
+                match (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        output.push(high << 4 | low);
+                        i += 3;
+                        continue;
+                    }
+                    _ => output.push(b'%'),
+                }
+            }
+            b => output.push(b),
+        }
+        i += 1;
+    }
+
+    Cow::Owned(output)
+}
+
+pub(crate) fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{decode, from_url, parse, query_string};
+
+    fn pairs(input: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        parse(input).map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+    }
+
+    #[test]
+    fn test_query_string() {
+        assert_eq!(query_string(b"/path?a=1#frag"), b"a=1");
+        assert_eq!(query_string(b"/path#frag?a=1"), b"");
+        assert_eq!(query_string(b"/path"), b"");
+        assert_eq!(query_string(b"http://example.com/?"), b"");
+    }
+
+    #[test]
+    fn test_pairs() {
+        assert_eq!(pairs(b"a=1&b=two+words&a=3&&flag&=empty"),
+                   vec![(b"a".to_vec(), b"1".to_vec()),
+                        (b"b".to_vec(), b"two words".to_vec()),
+                        (b"a".to_vec(), b"3".to_vec()),
+                        (b"flag".to_vec(), b"".to_vec()),
+                        (b"".to_vec(), b"empty".to_vec())]);
+
+        assert_eq!(from_url(b"/?x%3Dy=%26").collect::<Vec<_>>(),
+                   vec![(Cow::Owned(b"x=y".to_vec()), Cow::Owned(b"&".to_vec()))]);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert!(match decode(b"plain") {
+            Cow::Borrowed(b) => b == b"plain",
+            Cow::Owned(_) => false,
+        });
+        assert_eq!(decode(b"%E2%9C%93+ok"), "\u{2713} ok".as_bytes());
+        assert_eq!(decode(b"100%"), &b"100%"[..]);
+        assert_eq!(decode(b"%zz%4"), &b"%zz%4"[..]);
+        assert_eq!(decode(b"%4a%4A"), &b"JJ"[..]);
+    }
+}
@@ -0,0 +1,593 @@
+//! A small blocking HTTP/1.1 server, enabled with the `server` feature.
+//!
+//! Each connection is served on its own thread by a `ServerConnection`, and
+//! each request is read completely before it's passed to the handler. It's
+//! meant for admin endpoints and test fixtures rather than heavy traffic.
+//!
+//! # Example
+//! ```no_run
+//! use http_muncher::server::{Response, Server};
+//!
+//! Server::bind("127.0.0.1:8080").unwrap().serve(|request| {
+//!     if request.url == b"/health" {
+//!         Response::new(200).header("Content-Type", "text/plain").body("ok")
+//!     } else {
+//!         Response::new(404)
+//!     }
+//! });
+//! ```
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use headers::{self, Expectation, Headers};
+use writer;
+use {ConnectionError, ServerConnection, ServerEvent};
+
+/// A complete request passed to the handler.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The method, such as `GET`.
+    pub method: &'static str,
+    /// The raw request target, which `url::Url` can split into components.
+    pub url: Vec<u8>,
+    /// The HTTP version as `(major, minor)`.
+    pub version: (u16, u16),
+    /// The request headers.
+    pub headers: Headers,
+    /// The whole request body, decoded from chunks if it was chunked.
+    pub body: Vec<u8>,
+    /// The address of the client.
+    pub remote_addr: SocketAddr,
+}
+
+/// A response returned by the handler.
+///
+/// `Content-Length` and `Connection` are set by the server, so any framing
+/// headers among `headers` are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status code.
+    pub status: u16,
+    /// The response headers.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// The response body, left out for `HEAD` requests.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates an empty response with `status`, which has to be between 200 and 999.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header.
+    pub fn header<V: Into<Vec<u8>>>(mut self, name: &str, value: V) -> Response {
+        self.headers.push((name.to_owned(), value.into()));
+        self
+    }
+
+    /// Sets the body.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Limits {
+    read_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_body_size: usize,
+    max_connections: usize,
+}
+
+/// A blocking server running a handler for each request.
+///
+/// By default connections time out after 30 seconds without reading or
+/// writing anything, a request has to arrive within 60 seconds of its first
+/// byte, request bodies may be up to 1 MiB, and up to 256
+/// connections are served at once; further connections get a `503` response.
+/// Heads are limited by http-parser to 80 KiB. Tunnels aren't supported, so
+/// `CONNECT` requests get a `501` response.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    limits: Limits,
+}
+
+impl Server {
+    /// Creates a server listening on `addr`.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            limits: Limits {
+                read_timeout: Some(Duration::from_secs(30)),
+                request_timeout: Some(Duration::from_secs(60)),
+                write_timeout: Some(Duration::from_secs(30)),
+                max_body_size: 1024 * 1024,
+                max_connections: 256,
+            },
+        })
+    }
+
+    /// Sets how long a connection may wait for data from the client, or `None` to wait forever.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Server {
+        self.limits.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long reading the head and body of a request may take from its
+    /// first byte, or `None` to wait forever.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Server {
+        self.limits.request_timeout = timeout;
+        self
+    }
+
+    /// Sets how long writing a response may block, or `None` to wait forever.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Server {
+        self.limits.write_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum size of a request body in bytes.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Server {
+        self.limits.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets the maximum number of connections served at once.
+    pub fn max_connections(mut self, max_connections: usize) -> Server {
+        self.limits.max_connections = max_connections;
+        self
+    }
+
+    /// Returns the address the server is listening on, e.g. to find the port picked for port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves connections on the current thread, forever.
+    pub fn serve<F>(self, handler: F)
+        where F: Fn(Request) -> Response + Send + Sync + 'static
+    {
+        run(self.listener, self.limits, Arc::new(handler), Arc::new(AtomicBool::new(false)));
+    }
+
+    /// Serves connections on a background thread until the returned handle is shut down or dropped.
+    pub fn spawn<F>(self, handler: F) -> io::Result<ServerHandle>
+        where F: Fn(Request) -> Response + Send + Sync + 'static
+    {
+        let mut addr = self.listener.local_addr()?;
+        // Unspecified addresses can't be connected to everywhere, see `ServerHandle::stop`.
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+            _ => {}
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let handler = Arc::new(handler);
+            let (listener, limits) = (self.listener, self.limits);
+            thread::Builder::new()
+                .name("http-server".to_owned())
+                .spawn(move || run(listener, limits, handler, stop))?
+        };
+
+        Ok(ServerHandle {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A server running on a background thread, returned by `Server::spawn`.
+///
+/// Dropping the handle shuts the server down. Connections that are being
+/// served at that point are finished on their own threads.
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server can be reached at.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections and waits for the server thread to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            // Wake up the thread blocked in `accept`.
+            let _ = TcpStream::connect(self.addr);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Decrements the number of open connections when a connection thread ends.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn run<F>(listener: TcpListener, limits: Limits, handler: Arc<F>, stop: Arc<AtomicBool>)
+    where F: Fn(Request) -> Response + Send + Sync + 'static
+{
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let guard = ConnectionGuard(connections.clone());
+        if connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+            // This blocks accepting other connections, so the write has a
+            // short timeout and the client's input isn't drained.
+            let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+            let _ = send_error(&mut stream, 503).and_then(|()| stream.shutdown(Shutdown::Write));
+            continue;
+        }
+
+        let (handler, limits) = (handler.clone(), limits.clone());
+        let _ = thread::Builder::new().name("http-connection".to_owned()).spawn(move || {
+            let _guard = guard;
+            let _ = serve_connection(stream, &*handler, &limits);
+        });
+    }
+}
+
+fn serve_connection<F>(mut stream: TcpStream, handler: &F, limits: &Limits) -> io::Result<()>
+    where F: Fn(Request) -> Response
+{
+    stream.set_write_timeout(limits.write_timeout)?;
+    stream.set_nodelay(true)?;
+    let remote_addr = stream.peer_addr()?;
+
+    let mut conn = ServerConnection::new();
+    let mut request = None;
+    // When the first byte of the request being read arrived.
+    let mut started: Option<Instant> = None;
+    let mut buf = [0; 8192];
+
+    loop {
+        let event = match conn.next_event() {
+            Ok(event) => event,
+            Err(ConnectionError::Http(_)) => return write_error(&mut stream, 400),
+            Err(err) => return Err(io::Error::other(err)),
+        };
+
+        match event {
+            ServerEvent::RequestHead(head) => {
+                // Tunnels aren't supported, so a successful response couldn't be sent.
+                if head.method == "CONNECT" {
+                    return write_error(&mut stream, 501);
+                }
+                if matches!(head.headers.content_length(), Some(Ok(length)) if length > limits.max_body_size as u64) {
+                    return write_error(&mut stream, 413);
+                }
+                // The body is always wanted, so there's no need to wait for it to be accepted.
+                if head.version >= (1, 1) && head.headers.expect() == Some(Ok(Expectation::Continue)) {
+                    writer::write_continue(&mut stream)?;
+                }
+
+                request = Some(Request {
+                    method: head.method,
+                    url: head.url,
+                    version: head.version,
+                    headers: head.headers,
+                    body: Vec::new(),
+                    remote_addr,
+                });
+            }
+            ServerEvent::Body(data) => {
+                if let Some(ref mut request) = request {
+                    if request.body.len() + data.len() > limits.max_body_size {
+                        return write_error(&mut stream, 413);
+                    }
+                    request.body.extend_from_slice(&data);
+                }
+            }
+            ServerEvent::RequestEnd => {
+                started = None;
+                if let Some(request) = request.take() {
+                    let (is_head, version) = (request.method == "HEAD", request.version);
+                    let response = panic::catch_unwind(AssertUnwindSafe(|| handler(request)))
+                        .unwrap_or_else(|_| Response::new(500));
+                    write_response(&mut stream, &mut conn, response, is_head, version)?;
+                }
+            }
+            ServerEvent::NeedMoreData => {
+                let timeout = match (started, limits.request_timeout) {
+                    (Some(started), Some(request_timeout)) => {
+                        let remaining = request_timeout.saturating_sub(started.elapsed());
+                        if remaining == Duration::ZERO {
+                            return write_error(&mut stream, 408);
+                        }
+                        Some(limits.read_timeout.map_or(remaining, |timeout| timeout.min(remaining)))
+                    }
+                    _ => limits.read_timeout,
+                };
+                stream.set_read_timeout(timeout)?;
+
+                match stream.read(&mut buf) {
+                    Ok(size) => {
+                        if size > 0 && started.is_none() {
+                            started = Some(Instant::now());
+                        }
+                        conn.receive(&buf[..size]);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(ref err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                        // Only a client that's in the middle of a request is told about it.
+                        return if started.is_some() { write_error(&mut stream, 408) } else { Ok(()) };
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            ServerEvent::Paused | ServerEvent::Close | ServerEvent::Upgrade(_) => {
+                return close(&mut stream);
+            }
+        }
+    }
+}
+
+/// Writes `response` to a request of `version` with framing headers for `conn`, and records it there.
+fn write_response(stream: &mut TcpStream, conn: &mut ServerConnection, response: Response, is_head: bool,
+                  version: (u16, u16)) -> io::Result<()> {
+    // Interim and protocol switching responses aren't supported.
+    let mut response = if response.status < 200 { Response::new(500) } else { response };
+
+    let closes = response.headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("connection") &&
+            headers::connection(value).is_ok_and(|options| options.iter().any(|option| option.eq_ignore_ascii_case(b"close")))
+    });
+    let keep_alive = conn.keep_alive() && !closes;
+    let has_body = response.status != 204 && response.status != 304;
+
+    let mut out = Vec::new();
+    if write_head(&mut out, &response, version, keep_alive, has_body).is_err() {
+        // A header the handler set is invalid.
+        response = Response::new(500);
+        out.clear();
+        write_head(&mut out, &response, version, keep_alive, true)?;
+    }
+    if has_body && !is_head {
+        out.extend_from_slice(&response.body);
+    }
+
+    stream.write_all(&out)?;
+    stream.flush()?;
+
+    conn.start_response(response.status, keep_alive)
+        .and_then(|()| conn.end_response())
+        .map_err(io::Error::other)
+}
+
+fn write_head(out: &mut Vec<u8>, response: &Response, version: (u16, u16), keep_alive: bool, has_body: bool) -> io::Result<()> {
+    let length = response.body.len().to_string();
+
+    let mut headers: Vec<(&[u8], &[u8])> = response.headers.iter()
+        .filter(|(name, _)| !["content-length", "transfer-encoding", "connection"].iter().any(|framing| name.eq_ignore_ascii_case(framing)))
+        .map(|(name, value)| (name.as_bytes(), &value[..]))
+        .collect();
+    if has_body {
+        headers.push((b"Content-Length", length.as_bytes()));
+    }
+    if !keep_alive {
+        headers.push((b"Connection", b"close"));
+    } else if version < (1, 1) {
+        headers.push((b"Connection", b"keep-alive"));
+    }
+
+    writer::write_response_head(out, response.status, writer::reason_phrase(response.status), headers)
+}
+
+/// Writes an empty error response and closes the connection.
+fn write_error(stream: &mut TcpStream, status: u16) -> io::Result<()> {
+    send_error(stream, status)?;
+    close(stream)
+}
+
+/// Writes an empty error response announcing that the connection closes.
+fn send_error(stream: &mut TcpStream, status: u16) -> io::Result<()> {
+    let mut out = Vec::new();
+    writer::write_response_head(&mut out, status, writer::reason_phrase(status),
+                                [("Content-Length", "0"), ("Connection", "close")])?;
+
+    stream.write_all(&out)
+}
+
+/// Closes the sending side of the connection once the last response has been written.
+fn close(stream: &mut TcpStream) -> io::Result<()> {
+    stream.shutdown(Shutdown::Write)?;
+
+    // Closing a socket with unread data resets the connection, which can
+    // discard the response before the client reads it, so read what the
+    // client is still sending first, for up to a second.
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut buf = [0; 8192];
+    let mut drained = 0;
+    while drained < 64 * 1024 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            break;
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(size) => drained += size,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, SocketAddr, TcpStream};
+    use std::str;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Response, Server, ServerHandle};
+
+    fn spawn() -> ServerHandle {
+        Server::bind("127.0.0.1:0").unwrap()
+            .max_body_size(16)
+            .spawn(|request| {
+                match &request.url[..] {
+                    b"/panic" => panic!("handler failed"),
+                    b"/close" => Response::new(200).header("Connection", "close"),
+                    b"/bad-header" => Response::new(200).header("X-Bad", "a\r\nb"),
+                    url => Response::new(200)
+                        .header("Content-Length", "1000")
+                        .body([request.method.as_bytes(), b" ", url, b" ", &request.body].concat()),
+                }
+            })
+            .unwrap()
+    }
+
+    /// Sends `request`, closes the sending side and returns everything the server sends back.
+    fn exchange(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let server = spawn();
+
+        let response = exchange(server.local_addr(), b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                                                       POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n\
+                                                       HEAD /c HTTP/1.1\r\nConnection: close\r\n\r\n\
+                                                       GET /ignored HTTP/1.1\r\n\r\n");
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nGET /a \
+                              HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nPOST /b body\
+                              HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\n");
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn test_keep_alive_and_close() {
+        let server = spawn();
+
+        let response = exchange(server.local_addr(), b"GET /a HTTP/1.0\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\nGET /a ");
+
+        let response = exchange(server.local_addr(), b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /b HTTP/1.0\r\n\r\n");
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: keep-alive\r\n\r\nGET /a \
+                              HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\nGET /b ");
+
+        let response = exchange(server.local_addr(), b"GET /close HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let server = spawn();
+
+        let response = exchange(server.local_addr(), b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi");
+        assert_eq!(response, "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nPUT /a hi");
+    }
+
+    #[test]
+    fn test_errors() {
+        let server = spawn();
+        let addr = server.local_addr();
+        let error = |status: &str| format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+
+        assert_eq!(exchange(addr, b"NOT HTTP\r\n\r\n"), error("400 Bad Request"));
+        assert_eq!(exchange(addr, b"CONNECT example.com:443 HTTP/1.1\r\n\r\n"), error("501 Not Implemented"));
+        assert_eq!(exchange(addr, b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n"), error("413 Content Too Large"));
+        assert_eq!(exchange(addr, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n"), error("400 Bad Request"));
+        assert_eq!(exchange(addr, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n01234567890123456\r\n0\r\n\r\n"),
+                   error("413 Content Too Large"));
+
+        assert_eq!(exchange(addr, b"GET /panic HTTP/1.1\r\n\r\n"), "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(exchange(addr, b"GET /bad-header HTTP/1.1\r\n\r\n"), "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .read_timeout(Some(Duration::from_secs(10)))
+            .request_timeout(Some(Duration::from_millis(100)))
+            .spawn(|_| Response::new(204))
+            .unwrap();
+
+        // Each byte arrives well within the read timeout, but the head never ends.
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nX-Slow: ").unwrap();
+        for _ in 0..20 {
+            thread::sleep(Duration::from_millis(20));
+            let _ = stream.write_all(b"a");
+        }
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "HTTP/1.1 408 Request Timeout\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .max_connections(1)
+            .spawn(|_| Response::new(204))
+            .unwrap();
+
+        // Keeps the only connection open.
+        let mut first = TcpStream::connect(server.local_addr()).unwrap();
+        first.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = [0; 25];
+        first.read_exact(&mut response).unwrap();
+        assert_eq!(str::from_utf8(&response).unwrap(), "HTTP/1.1 204 No Content\r\n");
+
+        assert_eq!(exchange(server.local_addr(), b""),
+                   "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+}
@@ -0,0 +1,494 @@
+//! A sans-I/O state machine for the server side of a connection.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use error::{ConnectionError, HttpError};
+use headers::Headers;
+use {Parser, ParserHandler};
+
+/// The head of a request read by a `ServerConnection`.
+#[derive(Debug, Clone)]
+pub struct RequestHead {
+    /// The method, such as `GET`.
+    pub method: &'static str,
+    /// The raw request target, which `url::Url` can split into components.
+    pub url: Vec<u8>,
+    /// The HTTP version as `(major, minor)`.
+    pub version: (u16, u16),
+    /// The request headers.
+    pub headers: Headers,
+    /// `false` if the client asked to close the connection after this request.
+    pub keep_alive: bool,
+    /// `true` if the client asked to switch protocols, with `Upgrade` or `CONNECT`.
+    pub upgrade: bool,
+}
+
+/// An event returned by `ServerConnection::next_event`.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// The head of a new request has been read.
+    RequestHead(RequestHead),
+    /// A piece of the request body.
+    Body(Vec<u8>),
+    /// The request is complete.
+    RequestEnd,
+    /// All received data has been read; pass more to `receive`.
+    NeedMoreData,
+    /// Nothing more can be read until the response to the current request is finished.
+    Paused,
+    /// The connection should be closed once the response has been written.
+    Close,
+    /// The connection has switched to another protocol. Holds the data
+    /// received after the request, which belongs to the new protocol.
+    Upgrade(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestState {
+    Idle,
+    Head,
+    Body,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseState {
+    Idle,
+    Sending,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Open,
+    Switched,
+    Closed,
+}
+
+/// Turns parser callbacks into events, pausing the parser after each request.
+#[derive(Debug)]
+struct Collector {
+    events: VecDeque<ServerEvent>,
+    url: Vec<u8>,
+    headers: Headers,
+    request: RequestState,
+    method: &'static str,
+    keep_alive: bool,
+    upgrade: bool,
+}
+
+impl ParserHandler for Collector {
+    fn on_message_begin(&mut self, _: &mut Parser) -> bool {
+        self.request = RequestState::Head;
+        self.url.clear();
+        self.headers.clear();
+        true
+    }
+
+    fn on_url(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.url.extend_from_slice(data);
+        true
+    }
+
+    fn on_header_field(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.headers.on_header_field(data);
+        true
+    }
+
+    fn on_header_value(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.headers.on_header_value(data);
+        true
+    }
+
+    fn on_headers_complete(&mut self, parser: &mut Parser) -> bool {
+        self.headers.on_headers_complete();
+        self.request = RequestState::Body;
+        self.method = parser.http_method();
+        self.keep_alive = parser.should_keep_alive();
+        self.upgrade = parser.is_upgrade();
+
+        self.events.push_back(ServerEvent::RequestHead(RequestHead {
+            method: self.method,
+            url: mem::take(&mut self.url),
+            version: parser.http_version(),
+            headers: mem::take(&mut self.headers),
+            keep_alive: self.keep_alive,
+            upgrade: self.upgrade,
+        }));
+        true
+    }
+
+    fn on_body(&mut self, _: &mut Parser, data: &[u8]) -> bool {
+        self.events.push_back(ServerEvent::Body(data.to_vec()));
+        true
+    }
+
+    fn on_message_complete(&mut self, parser: &mut Parser) -> bool {
+        self.events.push_back(ServerEvent::RequestEnd);
+        self.request = RequestState::Done;
+        self.keep_alive = parser.should_keep_alive();
+        parser.pause();
+        true
+    }
+}
+
+/// The server side of an HTTP/1.1 connection, without any I/O.
+///
+/// Data read from the socket is passed to `receive`, and `next_event` turns
+/// it into events for one request at a time. The application reports the
+/// response it writes with `start_response` and `end_response`, and the
+/// connection decides from both sides when the next request can be read,
+/// when the connection has to be closed and when it's handed over to another
+/// protocol, in the manner of Python's h11.
+///
+/// The next request isn't read until the response to the current one has
+/// been finished: until then `next_event` returns `Paused`.
+///
+/// # Example
+/// ```
+/// use http_muncher::{ServerConnection, ServerEvent};
+///
+/// let mut conn = ServerConnection::new();
+/// conn.receive(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+///
+/// match conn.next_event().unwrap() {
+///     ServerEvent::RequestHead(head) => assert_eq!(head.url, b"/index.html"),
+///     event => panic!("unexpected {:?}", event),
+/// }
+/// assert!(matches!(conn.next_event().unwrap(), ServerEvent::RequestEnd));
+/// assert!(matches!(conn.next_event().unwrap(), ServerEvent::Paused));
+///
+/// // The response is written to the socket here.
+/// conn.start_response(200, true).unwrap();
+/// conn.end_response().unwrap();
+///
+/// assert!(matches!(conn.next_event().unwrap(), ServerEvent::Close));
+/// ```
+#[derive(Debug)]
+pub struct ServerConnection {
+    parser: Parser,
+    handler: Collector,
+    buffer: Vec<u8>,
+    eof: bool,
+    response: ResponseState,
+    response_keep_alive: bool,
+    switching: bool,
+    state: State,
+}
+
+impl Default for ServerConnection {
+    fn default() -> ServerConnection {
+        ServerConnection::new()
+    }
+}
+
+impl ServerConnection {
+    /// Creates the state machine for a new connection, before any request has been read.
+    pub fn new() -> ServerConnection {
+        ServerConnection {
+            parser: Parser::request(),
+            handler: Collector {
+                events: VecDeque::new(),
+                url: Vec::new(),
+                headers: Headers::new(),
+                request: RequestState::Idle,
+                method: "",
+                keep_alive: true,
+                upgrade: false,
+            },
+            buffer: Vec::new(),
+            eof: false,
+            response: ResponseState::Idle,
+            response_keep_alive: true,
+            switching: false,
+            state: State::Open,
+        }
+    }
+
+    /// Adds data received from the client. Empty `data` means that the client
+    /// has closed its side of the connection.
+    pub fn receive(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.eof = true;
+        } else {
+            self.buffer.extend_from_slice(data);
+        }
+    }
+
+    /// Returns the next event.
+    ///
+    /// A parsing error closes the connection; the application may still write
+    /// an error response, such as `400 Bad Request`, before closing it.
+    pub fn next_event(&mut self) -> Result<ServerEvent, ConnectionError> {
+        loop {
+            if let Some(event) = self.handler.events.pop_front() {
+                return Ok(event);
+            }
+
+            match self.state {
+                State::Closed => return Ok(ServerEvent::Close),
+                State::Switched => return Ok(ServerEvent::Paused),
+                State::Open => {}
+            }
+
+            if self.switching && self.handler.request == RequestState::Done {
+                self.state = State::Switched;
+                return Ok(ServerEvent::Upgrade(mem::take(&mut self.buffer)));
+            }
+
+            if self.response == ResponseState::Done && !self.keep_alive() {
+                self.state = State::Closed;
+                continue;
+            }
+
+            if self.handler.request == RequestState::Done {
+                if self.response != ResponseState::Done {
+                    return Ok(ServerEvent::Paused);
+                }
+
+                self.next_cycle();
+                continue;
+            }
+
+            if self.buffer.is_empty() {
+                if !self.eof {
+                    return Ok(ServerEvent::NeedMoreData);
+                }
+
+                // The client has closed the connection, which `finish`
+                // reports as an error in the middle of a request.
+                self.state = State::Closed;
+                if self.handler.request != RequestState::Idle {
+                    self.parser.finish(&mut self.handler)?;
+                }
+                continue;
+            }
+
+            self.parse()?;
+        }
+    }
+
+    /// Records that a response with `status` is being sent to the current
+    /// request. `keep_alive` is `false` if the response closes the connection.
+    ///
+    /// Interim `1xx` responses can be sent before the final one. A `101`
+    /// response to an upgrade request, or a `2xx` response to `CONNECT`,
+    /// switches protocols and needs no `end_response`.
+    pub fn start_response(&mut self, status: u16, keep_alive: bool) -> Result<(), ConnectionError> {
+        if self.state != State::Open || matches!(self.handler.request, RequestState::Idle | RequestState::Head) {
+            return Err(ConnectionError::State("no request to respond to"));
+        }
+        if self.response != ResponseState::Idle {
+            return Err(ConnectionError::State("a response has already been started"));
+        }
+
+        let switches = self.handler.upgrade &&
+            (status == 101 || self.handler.method == "CONNECT" && status / 100 == 2);
+
+        if switches {
+            self.switching = true;
+            self.response = ResponseState::Done;
+        } else if status / 100 != 1 {
+            self.response = ResponseState::Sending;
+            self.response_keep_alive = keep_alive;
+        }
+        Ok(())
+    }
+
+    /// Records that the response has been sent completely.
+    pub fn end_response(&mut self) -> Result<(), ConnectionError> {
+        if self.response != ResponseState::Sending {
+            return Err(ConnectionError::State("no response has been started"));
+        }
+
+        self.response = ResponseState::Done;
+        Ok(())
+    }
+
+    /// Checks if the connection can be used for another request after the current one.
+    ///
+    /// The client closing its side doesn't change this, since pipelined
+    /// requests received before that are still read.
+    pub fn keep_alive(&self) -> bool {
+        self.handler.keep_alive && self.response_keep_alive
+    }
+
+    /// Returns the parser reading the requests.
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    fn parse(&mut self) -> Result<(), ConnectionError> {
+        let size = self.parser.parse(&mut self.handler, &self.buffer);
+        self.buffer.drain(..size);
+
+        if self.parser.has_error() && !self.parser.is_paused() {
+            self.state = State::Closed;
+            return Err(ConnectionError::Http(HttpError::new(self.parser.http_errnum())));
+        }
+        Ok(())
+    }
+
+    fn next_cycle(&mut self) {
+        self.handler.request = RequestState::Idle;
+        self.handler.keep_alive = true;
+        self.handler.upgrade = false;
+        self.response = ResponseState::Idle;
+        self.response_keep_alive = true;
+        self.parser.unpause();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerConnection, ServerEvent};
+    use error::ConnectionError;
+
+    fn describe(event: &ServerEvent) -> String {
+        match *event {
+            ServerEvent::RequestHead(ref head) => format!("head {} {}", head.method, String::from_utf8_lossy(&head.url)),
+            ServerEvent::Body(ref data) => format!("body {}", String::from_utf8_lossy(data)),
+            ServerEvent::RequestEnd => "end".to_owned(),
+            ServerEvent::NeedMoreData => "need more data".to_owned(),
+            ServerEvent::Paused => "paused".to_owned(),
+            ServerEvent::Close => "close".to_owned(),
+            ServerEvent::Upgrade(ref data) => format!("upgrade {}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Returns events up to the first one that needs an action from the application.
+    fn events(conn: &mut ServerConnection) -> Vec<String> {
+        let mut events = Vec::new();
+
+        loop {
+            let event = conn.next_event().unwrap();
+            events.push(describe(&event));
+
+            if !matches!(event, ServerEvent::RequestHead(_) | ServerEvent::Body(_) | ServerEvent::RequestEnd) {
+                return events;
+            }
+        }
+    }
+
+    fn respond(conn: &mut ServerConnection) {
+        conn.start_response(200, true).unwrap();
+        conn.end_response().unwrap();
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let data = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+                     GET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n";
+
+        for split in 1..data.len() {
+            let mut conn = ServerConnection::new();
+            let mut log = Vec::new();
+
+            for chunk in &[&data[..split], &data[split..]] {
+                conn.receive(chunk);
+
+                loop {
+                    match conn.next_event().unwrap() {
+                        ServerEvent::Paused => respond(&mut conn),
+                        ServerEvent::NeedMoreData => break,
+                        event => log.push(describe(&event)),
+                    }
+                }
+            }
+
+            // The body arrives in two pieces if it's split.
+            let body: String = log.iter().filter(|event| event.starts_with("body ")).map(|event| &event[5..]).collect();
+            log.retain(|event| !event.starts_with("body "));
+
+            assert_eq!(body, "abc", "split at {}", split);
+            assert_eq!(log, vec!["head POST /a", "end", "head GET /b", "end"], "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let mut conn = ServerConnection::new();
+        conn.receive(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\nGET /ignored HTTP/1.1\r\n\r\n");
+        assert_eq!(events(&mut conn), vec!["head GET /", "end", "paused"]);
+        assert!(!conn.keep_alive());
+
+        respond(&mut conn);
+        assert_eq!(events(&mut conn), vec!["close"]);
+
+        // The server can also close the connection.
+        let mut conn = ServerConnection::new();
+        conn.receive(b"GET / HTTP/1.1\r\n\r\nGET /ignored HTTP/1.1\r\n\r\n");
+        assert_eq!(events(&mut conn), vec!["head GET /", "end", "paused"]);
+
+        conn.start_response(503, false).unwrap();
+        conn.end_response().unwrap();
+        assert_eq!(events(&mut conn), vec!["close"]);
+    }
+
+    #[test]
+    fn test_early_response_before_body() {
+        let mut conn = ServerConnection::new();
+        conn.receive(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n12345");
+        assert_eq!(events(&mut conn), vec!["head POST /", "body 12345", "need more data"]);
+
+        conn.start_response(413, false).unwrap();
+        conn.end_response().unwrap();
+        assert_eq!(events(&mut conn), vec!["close"]);
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut conn = ServerConnection::new();
+        conn.receive(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nframes");
+        assert_eq!(events(&mut conn), vec!["head GET /chat", "end", "paused"]);
+
+        conn.start_response(101, true).unwrap();
+        assert_eq!(events(&mut conn), vec!["upgrade frames"]);
+        assert_eq!(events(&mut conn), vec!["paused"]);
+
+        // Declining the upgrade carries on with HTTP.
+        let mut conn = ServerConnection::new();
+        conn.receive(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(events(&mut conn), vec!["head GET /chat", "end", "paused"]);
+
+        respond(&mut conn);
+        assert_eq!(events(&mut conn), vec!["head GET /", "end", "paused"]);
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut conn = ServerConnection::new();
+        conn.receive(b"GET / HTTP/1.1\r\n\r\n");
+        conn.receive(b"");
+        assert_eq!(events(&mut conn), vec!["head GET /", "end", "paused"]);
+
+        respond(&mut conn);
+        assert_eq!(events(&mut conn), vec!["close"]);
+
+        let mut conn = ServerConnection::new();
+        conn.receive(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n12345");
+        conn.receive(b"");
+        assert_eq!(describe(&conn.next_event().unwrap()), "head POST /");
+        assert_eq!(describe(&conn.next_event().unwrap()), "body 12345");
+        match conn.next_event() {
+            Err(ConnectionError::Http(err)) => assert_eq!(err.name(), "HPE_INVALID_EOF_STATE"),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(matches!(conn.next_event(), Ok(ServerEvent::Close)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut conn = ServerConnection::new();
+        assert_eq!(conn.start_response(200, true), Err(ConnectionError::State("no request to respond to")));
+        assert_eq!(conn.end_response(), Err(ConnectionError::State("no response has been started")));
+
+        conn.receive(b"XYZ / HTTP/1.1\r\n\r\n");
+        match conn.next_event() {
+            Err(ConnectionError::Http(err)) => assert_eq!(err.name(), "HPE_INVALID_METHOD"),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(matches!(conn.next_event(), Ok(ServerEvent::Close)));
+    }
+}
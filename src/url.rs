@@ -0,0 +1,321 @@
+//! URL parsing and path normalization for request targets received in `on_url`.
+//!
+//! As with the `query` module, collect all `on_url` fragments into one buffer
+//! before parsing it.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+use libc;
+
+use ffi::*;
+use query::hex_value;
+
+/// A request target split into its components by http-parser.
+///
+/// Components are slices of the raw URL and aren't decoded.
+///
+/// # Example
+/// ```
+/// use http_muncher::url::Url;
+///
+/// let url = Url::parse(b"http://example.com:8080/a/b?c=d#e").unwrap();
+///
+/// assert_eq!(url.host(), Some(&b"example.com"[..]));
+/// assert_eq!(url.port(), Some(8080));
+/// assert_eq!(url.path(), Some(&b"/a/b"[..]));
+/// assert_eq!(url.query(), Some(&b"c=d"[..]));
+/// ```
+#[derive(Clone, Copy)]
+pub struct Url<'a> {
+    raw: &'a [u8],
+    parsed: HttpParserUrl,
+}
+
+impl<'a> Url<'a> {
+    /// Parses an origin-form (`/path?query`) or absolute-form (`http://host/path`) request target.
+    pub fn parse(raw: &'a [u8]) -> Result<Url<'a>, PathError> {
+        Url::parse_url(raw, false)
+    }
+
+    /// Parses an authority-form (`host:port`) request target, as used by `CONNECT`.
+    pub fn parse_connect(raw: &'a [u8]) -> Result<Url<'a>, PathError> {
+        Url::parse_url(raw, true)
+    }
+
+    fn parse_url(raw: &'a [u8], is_connect: bool) -> Result<Url<'a>, PathError> {
+        // Offsets into the URL are 16-bit.
+        if raw.len() > u16::MAX as usize {
+            return Err(PathError::InvalidUrl);
+        }
+
+        let mut parsed = HttpParserUrl::default();
+        let res = unsafe {
+            http_parser_parse_url(raw.as_ptr() as *const libc::c_char,
+                                  raw.len() as libc::size_t,
+                                  is_connect as libc::c_int,
+                                  &mut parsed)
+        };
+
+        if res != 0 {
+            return Err(PathError::InvalidUrl);
+        }
+
+        Ok(Url { raw, parsed })
+    }
+
+    fn field(&self, field: usize) -> Option<&'a [u8]> {
+        if self.parsed.field_set & (1 << field) == 0 {
+            return None;
+        }
+
+        let data = self.parsed.field_data[field];
+        let start = data.off as usize;
+        Some(&self.raw[start..start + data.len as usize])
+    }
+
+    /// Returns the raw URL.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Returns the scheme, such as `http`.
+    pub fn schema(&self) -> Option<&'a [u8]> {
+        self.field(UF_SCHEMA)
+    }
+
+    /// Returns the user information before the `@` in the authority.
+    pub fn userinfo(&self) -> Option<&'a [u8]> {
+        self.field(UF_USERINFO)
+    }
+
+    /// Returns the host, without the brackets around an IPv6 address.
+    pub fn host(&self) -> Option<&'a [u8]> {
+        self.field(UF_HOST)
+    }
+
+    /// Returns the port, if the URL has one.
+    pub fn port(&self) -> Option<u16> {
+        self.field(UF_PORT).map(|_| self.parsed.port)
+    }
+
+    /// Returns the path, including the leading `/`.
+    pub fn path(&self) -> Option<&'a [u8]> {
+        self.field(UF_PATH)
+    }
+
+    /// Returns the query string without the leading `?`.
+    pub fn query(&self) -> Option<&'a [u8]> {
+        self.field(UF_QUERY)
+    }
+
+    /// Returns the fragment without the leading `#`.
+    pub fn fragment(&self) -> Option<&'a [u8]> {
+        self.field(UF_FRAGMENT)
+    }
+}
+
+impl<'a> fmt::Debug for Url<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Url")
+            .field("raw", &String::from_utf8_lossy(self.raw))
+            .finish()
+    }
+}
+
+/// Options for `normalize_path`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathOptions {
+    /// Fail with `PathError::EncodedSlash` on `%2F`, instead of decoding it to a path separator.
+    pub reject_encoded_slash: bool,
+    /// Fail with `PathError::EncodedNul` on `%00`.
+    pub reject_encoded_nul: bool,
+}
+
+/// An error returned by `Url::parse` and `normalize_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// http-parser couldn't parse the URL.
+    InvalidUrl,
+    /// The path contains an encoded `/` and `PathOptions::reject_encoded_slash` is set.
+    EncodedSlash,
+    /// The path contains an encoded NUL byte and `PathOptions::reject_encoded_nul` is set.
+    EncodedNul,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            PathError::InvalidUrl => "invalid URL",
+            PathError::EncodedSlash => "encoded slash in path",
+            PathError::EncodedNul => "encoded NUL byte in path",
+        })
+    }
+}
+
+impl Error for PathError {}
+
+/// A request path in its raw and normalized forms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizedPath<'a> {
+    /// The path as it was received, without the query string and fragment.
+    pub raw: &'a [u8],
+    /// The percent-decoded path with empty, `.` and `..` segments resolved.
+    /// It always starts with `/`, except for the `*` target of `OPTIONS` requests.
+    pub normalized: Cow<'a, [u8]>,
+}
+
+/// Returns a canonical path for a request target, to be used for routing and access control.
+///
+/// The path is percent-decoded first, so encoded dot segments (`%2E%2E`) are
+/// resolved like plain ones, and so are encoded slashes unless
+/// `PathOptions::reject_encoded_slash` is set. Repeated slashes are collapsed,
+/// and `..` never goes above the root. Malformed escapes are kept as is.
+///
+/// # Example
+/// ```
+/// use http_muncher::url::{normalize_path, PathOptions};
+///
+/// let path = normalize_path(b"/static//css/../%2E%2E/../etc/passwd?x=1", PathOptions::default()).unwrap();
+///
+/// assert_eq!(path.raw, &b"/static//css/../%2E%2E/../etc/passwd"[..]);
+/// assert_eq!(&*path.normalized, &b"/etc/passwd"[..]);
+/// ```
+pub fn normalize_path(url: &[u8], options: PathOptions) -> Result<NormalizedPath<'_>, PathError> {
+    let parsed = Url::parse(url)?;
+    let raw = parsed.path().unwrap_or(&[]);
+
+    if raw == b"*" {
+        return Ok(NormalizedPath { raw, normalized: Cow::Borrowed(raw) });
+    }
+
+    let decoded = percent_decode(raw, options)?;
+    let normalized = remove_dot_segments(&decoded);
+
+    Ok(NormalizedPath {
+        raw,
+        normalized: if normalized == raw {
+            Cow::Borrowed(raw)
+        } else {
+            Cow::Owned(normalized)
+        },
+    })
+}
+
+fn percent_decode(input: &[u8], options: PathOptions) -> Result<Vec<u8>, PathError> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        if input[i] == b'%' && i + 2 < input.len() {
+            if let (Some(high), Some(low)) = (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                let byte = high << 4 | low;
+
+                if byte == b'/' && options.reject_encoded_slash {
+                    return Err(PathError::EncodedSlash);
+                }
+                if byte == 0 && options.reject_encoded_nul {
+                    return Err(PathError::EncodedNul);
+                }
+
+                output.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        output.push(input[i]);
+        i += 1;
+    }
+
+    Ok(output)
+}
+
+/// Resolves `.` and `..` segments and drops empty ones, keeping a trailing slash.
+pub(crate) fn remove_dot_segments(path: &[u8]) -> Vec<u8> {
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in path.split(|&b| b == b'/') {
+        trailing_slash = true;
+
+        match segment {
+            b"" | b"." => {}
+            b".." => {
+                segments.pop();
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(path.len() + 1);
+    for segment in &segments {
+        normalized.push(b'/');
+        normalized.extend_from_slice(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push(b'/');
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_path, PathError, PathOptions, Url};
+
+    fn normalize(url: &[u8]) -> Vec<u8> {
+        normalize_path(url, PathOptions::default()).unwrap().normalized.into_owned()
+    }
+
+    #[test]
+    fn test_url_components() {
+        let url = Url::parse(b"https://user@example.com/p?q#f").unwrap();
+        assert_eq!(url.schema(), Some(&b"https"[..]));
+        assert_eq!(url.userinfo(), Some(&b"user"[..]));
+        assert_eq!(url.port(), None);
+        assert_eq!(url.fragment(), Some(&b"f"[..]));
+
+        let url = Url::parse_connect(b"example.com:443").unwrap();
+        assert_eq!(url.host(), Some(&b"example.com"[..]));
+        assert_eq!(url.port(), Some(443));
+        assert_eq!(url.path(), None);
+
+        assert_eq!(Url::parse(b"not a url").unwrap_err(), PathError::InvalidUrl);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize(b"/"), b"/");
+        assert_eq!(normalize(b"/a//b/./c"), b"/a/b/c");
+        assert_eq!(normalize(b"/a/b/../c/"), b"/a/c/");
+        assert_eq!(normalize(b"/a/.."), b"/");
+        assert_eq!(normalize(b"/a/."), b"/a/");
+        assert_eq!(normalize(b"/../../x"), b"/x");
+        assert_eq!(normalize(b"/%2e%2E/%2E/x%2Fy"), b"/x/y");
+        assert_eq!(normalize(b"/a/..%2F..%2Fb"), b"/b");
+        assert_eq!(normalize(b"/100%25%zz"), b"/100%%zz");
+        assert_eq!(normalize(b"http://example.com"), b"/");
+        assert_eq!(normalize(b"*"), b"*");
+    }
+
+    #[test]
+    fn test_normalized_path_is_borrowed() {
+        let path = normalize_path(b"/clean/path?q", PathOptions::default()).unwrap();
+        assert_eq!(path.raw, &b"/clean/path"[..]);
+        assert!(path.normalized.as_ptr() == path.raw.as_ptr());
+    }
+
+    #[test]
+    fn test_rejected_escapes() {
+        let options = PathOptions { reject_encoded_slash: true, reject_encoded_nul: true };
+
+        assert_eq!(normalize_path(b"/a%2fb", options), Err(PathError::EncodedSlash));
+        assert_eq!(normalize_path(b"/a%00", options), Err(PathError::EncodedNul));
+        assert_eq!(normalize(b"/a%00"), b"/a\0");
+    }
+}
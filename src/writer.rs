@@ -0,0 +1,221 @@
+//! Writing HTTP/1.1 message heads and chunked bodies.
+//!
+//! Names and values are checked before anything is written, so that a value
+//! taken from untrusted input can't inject extra headers or messages.
+
+use std::io::{self, Write};
+
+use headers::is_token;
+
+/// An interim `100 Continue` response, inviting the client to send the body.
+pub const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// A `417 Expectation Failed` response. It closes the connection, since the
+/// client may have started sending the body anyway.
+pub const EXPECTATION_FAILED: &[u8] = b"HTTP/1.1 417 Expectation Failed\r\n\
+                                        Content-Length: 0\r\n\
+                                        Connection: close\r\n\r\n";
+
+/// Writes a `100 Continue` response to `out`.
+pub fn write_continue<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(CONTINUE)
+}
+
+/// Writes a `417 Expectation Failed` response to `out`.
+pub fn write_expectation_failed<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(EXPECTATION_FAILED)
+}
+
+/// Writes a request line and headers, up to the empty line that ends the head.
+///
+/// Framing headers such as `Content-Length` or `Transfer-Encoding` aren't
+/// added: they have to be among `headers` if the request has a body.
+pub fn write_request_head<W, I, N, V>(out: &mut W, method: &str, target: &str, headers: I) -> io::Result<()>
+    where W: Write,
+          I: IntoIterator<Item = (N, V)>,
+          N: AsRef<[u8]>,
+          V: AsRef<[u8]>
+{
+    if !is_token(method.as_bytes()) {
+        return Err(invalid("method"));
+    }
+    if target.is_empty() || !target.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(invalid("request target"));
+    }
+
+    let mut head = Vec::new();
+    head.extend_from_slice(method.as_bytes());
+    head.push(b' ');
+    head.extend_from_slice(target.as_bytes());
+    head.extend_from_slice(b" HTTP/1.1\r\n");
+    write_headers(&mut head, headers)?;
+
+    out.write_all(&head)
+}
+
+/// Writes a status line and headers, up to the empty line that ends the head.
+///
+/// As with `write_request_head`, framing headers aren't added.
+pub fn write_response_head<W, I, N, V>(out: &mut W, status: u16, reason: &str, headers: I) -> io::Result<()>
+    where W: Write,
+          I: IntoIterator<Item = (N, V)>,
+          N: AsRef<[u8]>,
+          V: AsRef<[u8]>
+{
+    if !(100..1000).contains(&status) {
+        return Err(invalid("status code"));
+    }
+    if reason.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+        return Err(invalid("reason phrase"));
+    }
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason).into_bytes();
+    write_headers(&mut head, headers)?;
+
+    out.write_all(&head)
+}
+
+/// Returns the standard reason phrase for `status`, or an empty string for unknown codes.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+/// Writes `data` as a chunk of a chunked body. Empty `data` writes nothing,
+/// since an empty chunk would end the body.
+pub fn write_chunk<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    write!(out, "{:x}\r\n", data.len())?;
+    out.write_all(data)?;
+    out.write_all(b"\r\n")
+}
+
+/// Writes the last chunk that ends a chunked body, without trailers.
+pub fn write_last_chunk<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(b"0\r\n\r\n")
+}
+
+/// Appends `headers` and the empty line after them to `head`.
+fn write_headers<I, N, V>(head: &mut Vec<u8>, headers: I) -> io::Result<()>
+    where I: IntoIterator<Item = (N, V)>,
+          N: AsRef<[u8]>,
+          V: AsRef<[u8]>
+{
+    for (name, value) in headers {
+        let (name, value) = (name.as_ref(), value.as_ref());
+
+        if !is_token(name) {
+            return Err(invalid("header name"));
+        }
+        if value.iter().any(|&b| b == b'\r' || b == b'\n' || b == 0) {
+            return Err(invalid("header value"));
+        }
+
+        head.extend_from_slice(name);
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value);
+        head.extend_from_slice(b"\r\n");
+    }
+
+    head.extend_from_slice(b"\r\n");
+    Ok(())
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reason_phrase, write_chunk, write_continue, write_expectation_failed, write_last_chunk,
+                write_request_head, write_response_head};
+
+    #[test]
+    fn test_responses() {
+        let mut out = Vec::new();
+        write_continue(&mut out).unwrap();
+        write_expectation_failed(&mut out).unwrap();
+
+        assert_eq!(&out[..], &b"HTTP/1.1 100 Continue\r\n\r\n\
+                                HTTP/1.1 417 Expectation Failed\r\n\
+                                Content-Length: 0\r\nConnection: close\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn test_request_head() {
+        let mut out = Vec::new();
+        write_request_head(&mut out, "GET", "/index.html", [("Host", "example.com"), ("X-Empty", "")]).unwrap();
+        assert_eq!(out, b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nX-Empty: \r\n\r\n");
+
+        let mut out = Vec::new();
+        let no_headers: [(&str, &str); 0] = [];
+        assert!(write_request_head(&mut out, "GET /", "/", no_headers).is_err());
+        assert!(write_request_head(&mut out, "GET", "/a b", no_headers).is_err());
+        assert!(write_request_head(&mut out, "GET", "/", [("Host:", "a")]).is_err());
+        assert!(write_request_head(&mut out, "GET", "/", [("Host", "a\r\nX-Injected: 1")]).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_response_head() {
+        let mut out = Vec::new();
+        write_response_head(&mut out, 404, reason_phrase(404), [("Content-Length", "0")]).unwrap();
+        assert_eq!(out, b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+
+        let mut out = Vec::new();
+        write_response_head(&mut out, 299, reason_phrase(299), [("Content-Length", "0")]).unwrap();
+        assert_eq!(out, b"HTTP/1.1 299 \r\nContent-Length: 0\r\n\r\n");
+
+        assert!(write_response_head(&mut out, 1000, "", [("Content-Length", "0")]).is_err());
+        assert!(write_response_head(&mut out, 200, "OK\r\n", [("Content-Length", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut out = Vec::new();
+        write_chunk(&mut out, b"hello world!!!!!").unwrap();
+        write_chunk(&mut out, b"").unwrap();
+        write_last_chunk(&mut out).unwrap();
+        assert_eq!(out, b"10\r\nhello world!!!!!\r\n0\r\n\r\n");
+    }
+}